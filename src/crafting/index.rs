@@ -0,0 +1,241 @@
+use super::BlueprintID;
+
+/*
+A k-d tree over attribute vectors, for finding every blueprint within some L1
+distance of a query. Points are only ever added, each splitting on the next
+axis after its parent's.
+
+blueprints are often added in order (eg: loading a saved pool sorted by ID, or
+a run of ever heavier alloys), which would make a long chain. so whenever an
+insert lands deeper than 2·log2(n), the smallest subtree around it that is too
+deep for its size is rebuilt, split at the medians (as in a scapegoat tree).
+only many identical points can stay deeper than that.
+*/
+
+pub const DIMENSIONS: usize = 7;
+
+pub type AttributeVector = [u32; DIMENSIONS];
+
+#[derive(Debug,Clone)]
+struct Node {
+    point: AttributeVector,
+    id: BlueprintID,
+    axis: usize,
+    // below and at-or-above this node's value on its axis
+    lower: Option<usize>,
+    upper: Option<usize>,
+}
+
+#[derive(Debug,Clone)]
+pub struct AttributeIndex {
+    nodes: Vec<Node>,
+}
+
+fn l1(a: &AttributeVector, b: &AttributeVector) -> u32 {
+    a.iter().zip(b.iter())
+    .map(|(x, y)| if x > y {x - y} else {y - x})
+    .sum()
+}
+
+// deeper than 2·log2(size) below a node with `size` nodes under it (itself included)
+fn too_deep(depth: usize, size: usize) -> bool {
+    depth > 0 && (depth as f64) > 2.0 * (size as f64).log2()
+}
+
+impl AttributeIndex {
+    pub fn new() -> AttributeIndex {
+        AttributeIndex {nodes: vec![]}
+    }
+
+    pub fn len(&self) -> usize {self.nodes.len()}
+
+    pub fn insert(&mut self, point: AttributeVector, id: BlueprintID) {
+        let new_index = self.nodes.len();
+        let mut axis = 0;
+        // from the root down to the new node
+        let mut path = vec![];
+        if !self.nodes.is_empty() {
+            let mut at = 0;
+            loop {
+                path.push(at);
+                let node = &mut self.nodes[at];
+                let slot = if point[node.axis] < node.point[node.axis] {&mut node.lower} else {&mut node.upper};
+                match *slot {
+                    Some(next) => at = next,
+                    None => {
+                        *slot = Some(new_index);
+                        axis = (node.axis + 1) % DIMENSIONS;
+                        break;
+                    },
+                }
+            }
+        }
+        self.nodes.push(Node {point: point, id: id, axis: axis, lower: None, upper: None});
+        path.push(new_index);
+        if too_deep(path.len() - 1, self.nodes.len()) {
+            self.rebalance(&path);
+        }
+    }
+
+    fn subtree_size(&self, root: Option<usize>) -> usize {
+        let mut size = 0;
+        let mut stack: Vec<usize> = root.into_iter().collect();
+        while let Some(at) = stack.pop() {
+            size += 1;
+            stack.extend(self.nodes[at].lower);
+            stack.extend(self.nodes[at].upper);
+        }
+        size
+    }
+
+    // rebuilds the lowest node on `path` whose subtree is too deep for its size.
+    // there always is one, as the root is
+    fn rebalance(&mut self, path: &[usize]) {
+        let mut size = 1;
+        for (height, i) in (0..path.len() - 1).rev().enumerate() {
+            let (at, child) = (path[i], path[i + 1]);
+            let other = if self.nodes[at].lower == Some(child) {self.nodes[at].upper} else {self.nodes[at].lower};
+            size += 1 + self.subtree_size(other);
+            if too_deep(height + 1, size) {
+                self.rebuild(at);
+                return
+            }
+        }
+    }
+
+    // lays the subtree out again in the same slots, so its parent still points at it
+    fn rebuild(&mut self, root: usize) {
+        let mut slots = vec![];
+        let mut stack = vec![root];
+        while let Some(at) = stack.pop() {
+            slots.push(at);
+            stack.extend(self.nodes[at].lower);
+            stack.extend(self.nodes[at].upper);
+        }
+        let mut items: Vec<(AttributeVector, BlueprintID)> = slots.iter().map(|&i| (self.nodes[i].point, self.nodes[i].id)).collect();
+        // the first slot taken is the subtree's root
+        slots.reverse();
+        self.build(&mut items, &mut slots);
+    }
+
+    // splits on the axis the points are most spread along, as splitting on one
+    // where they are all the same (eg: Starch, for metals) would only add a level
+    fn build(&mut self, items: &mut [(AttributeVector, BlueprintID)], slots: &mut Vec<usize>) -> Option<usize> {
+        if items.is_empty() {return None}
+        let spread = |axis: usize| {
+            let values = items.iter().map(|&(p, _)| p[axis]);
+            values.clone().max().unwrap() - values.min().unwrap()
+        };
+        let mut axis = 0;
+        for a in 1..DIMENSIONS {
+            if spread(a) > spread(axis) {
+                axis = a;
+            }
+        }
+        items.sort_by_key(|&(p, _)| p[axis]);
+        // points equal to the split on its axis have to go above it, so split
+        // before or after the run of the median's value, whichever is nearer the middle
+        let half = items.len() / 2;
+        let value = items[half].0[axis];
+        let first = items.iter().position(|&(p, _)| p[axis] == value).unwrap();
+        let after = items.iter().rposition(|&(p, _)| p[axis] == value).unwrap() + 1;
+        let mid = if after < items.len() && after - half < half - first {after} else {first};
+
+        let slot = slots.pop().unwrap();
+        let (below, rest) = items.split_at_mut(mid);
+        let (point, id) = rest[0];
+        let lower = self.build(below, slots);
+        let upper = self.build(&mut rest[1..], slots);
+        self.nodes[slot] = Node {point: point, id: id, axis: axis, lower: lower, upper: upper};
+        Some(slot)
+    }
+
+    // every ID whose point is within `max_dist` of `query`, in no particular order
+    pub fn within_l1(&self, query: &AttributeVector, max_dist: u32) -> Vec<BlueprintID> {
+        let mut found = vec![];
+        let mut stack = if self.nodes.is_empty() {vec![]} else {vec![0]};
+        while let Some(at) = stack.pop() {
+            let node = &self.nodes[at];
+            if l1(query, &node.point) <= max_dist {
+                found.push(node.id);
+            }
+            // the distance along one axis alone already rules out a side
+            let (q, split) = (query[node.axis], node.point[node.axis]);
+            if let Some(lower) = node.lower {
+                if q < split.saturating_add(max_dist) {
+                    stack.push(lower);
+                }
+            }
+            if let Some(upper) = node.upper {
+                if q.saturating_add(max_dist) >= split {
+                    stack.push(upper);
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::{SeedableRng,Rng,Isaac64Rng};
+    use super::*;
+
+    fn depth(index: &AttributeIndex) -> usize {
+        let mut deepest = 0;
+        let mut stack = if index.nodes.is_empty() {vec![]} else {vec![(0, 0)]};
+        while let Some((at, d)) = stack.pop() {
+            deepest = ::std::cmp::max(deepest, d);
+            stack.extend(index.nodes[at].lower.map(|n| (n, d + 1)));
+            stack.extend(index.nodes[at].upper.map(|n| (n, d + 1)));
+        }
+        deepest
+    }
+
+    fn linear_scan(points: &[(AttributeVector, BlueprintID)], query: &AttributeVector, max_dist: u32) -> Vec<BlueprintID> {
+        points.iter().filter(|&&(p, _)| l1(query, &p) <= max_dist).map(|&(_, id)| id).collect()
+    }
+
+    #[test]
+    fn within_l1_matches_a_linear_scan() {
+        let mut rng = Isaac64Rng::from_seed(&[43]);
+        let mut index = AttributeIndex::new();
+        let mut points = vec![];
+        for id in 0..500 {
+            let mut p = [0; DIMENSIONS];
+            for v in p.iter_mut() {
+                // mostly small, and plenty of ties
+                *v = if rng.gen_weighted_bool(3) {0} else {rng.gen_range(0, 20)};
+            }
+            index.insert(p, id);
+            points.push((p, id));
+        }
+        assert_eq!(index.len(), 500);
+        for _ in 0..200 {
+            let mut query = [0; DIMENSIONS];
+            for v in query.iter_mut() {
+                *v = rng.gen_range(0, 25);
+            }
+            let max_dist = rng.gen_range(0, 60);
+            let mut found = index.within_l1(&query, max_dist);
+            found.sort();
+            assert_eq!(found, linear_scan(&points, &query, max_dist), "{:?} within {}", query, max_dist);
+        }
+    }
+
+    #[test]
+    fn sorted_inserts_stay_shallow() {
+        let mut index = AttributeIndex::new();
+        let mut points = vec![];
+        for id in 0..1000 {
+            let p = [id as u32, 5, id as u32 / 10, 0, 0, 0, 1000 - id as u32];
+            index.insert(p, id);
+            points.push((p, id));
+            let n = index.len() as f64;
+            assert!(depth(&index) as f64 <= 2.0 * n.log2(), "{} deep with {} points", depth(&index), n);
+        }
+        let mut found = index.within_l1(&[500, 5, 50, 0, 0, 0, 500], 30);
+        found.sort();
+        assert_eq!(found, linear_scan(&points, &[500, 5, 50, 0, 0, 0, 500], 30));
+    }
+}
@@ -0,0 +1,813 @@
+use std::collections::HashMap;
+use std::io::{self,BufRead,Read,Write};
+use ::serde_json;
+
+pub mod recipes;
+pub mod similarity;
+pub mod index;
+pub mod tech_tree;
+pub mod resources;
+use self::recipes::{Recipe,RecipeError,read_recipes};
+use self::similarity::{Similarity,Overlap};
+use self::index::{AttributeIndex,AttributeVector};
+
+/*
+A Blueprint is a kind of thing that can exist, eg: "flattened copper".
+Processes turn blueprints into other blueprints. The pool invents a new
+blueprint for every result that isn't close enough to one it already knows.
+*/
+
+pub type BlueprintID = u64;
+
+#[derive(Debug)]
+pub enum CraftError {
+    UnknownBlueprint(BlueprintID),
+    // the process takes a different number of inputs
+    WrongInputCount(Process, usize),
+    // eg: cutting something too short to cut
+    NotApplicable(Process),
+    UnknownRecipe(String),
+    // the blueprint doesn't meet the recipe's conditions
+    RecipeDoesNotApply(String),
+    Io(io::Error),
+    Serde(serde_json::Error),
+    // a saved pool from a schema this build doesn't know
+    UnsupportedVersion(u32),
+}
+
+impl From<io::Error> for CraftError {
+    fn from(e: io::Error) -> CraftError {CraftError::Io(e)}
+}
+
+impl From<serde_json::Error> for CraftError {
+    fn from(e: serde_json::Error) -> CraftError {CraftError::Serde(e)}
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Blueprint {
+    name : String,
+    attributes : Attributes,
+}
+
+impl Blueprint {
+    pub fn new(name : String, attributes : Attributes) -> Blueprint {
+        Blueprint {
+            name : name,
+            attributes : attributes,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {&self.name}
+    pub fn get_attributes(&self) -> &Attributes {&self.attributes}
+
+    #[inline]
+    pub fn get_att(&self, t : AttributeType) -> u32 {
+        self.attributes.get(t)
+    }
+}
+
+// absent attributes are 0, and 0 is never stored
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Attributes {
+    a : HashMap<AttributeType, u32>,
+}
+
+impl Attributes {
+    pub fn calc_area(&self) -> u32 {
+        self.get(AttributeType::Length) * self.get(AttributeType::Length)
+    }
+
+    // 0 for things without any area
+    pub fn density(&self) -> u32 {
+        match self.calc_area() {
+            0 => 0,
+            area => self.get(AttributeType::Mass) * 4 / area,
+        }
+    }
+
+    // 1.0 for identical attributes, towards 0.0 the more they differ
+    pub fn similarity_coefficient(&self, other : &Attributes) -> f32 {
+        let mut similarity = 0.0;
+        let mut difference = 0.0;
+
+        for att in self.a.keys() {
+            let (a, b) = (self.get(*att) as f32, other.get(*att) as f32);
+            difference += (a - b).abs();
+            similarity += if a < b {a} else {b};
+        }
+        for (k, v) in other.a.iter() {
+            if ! self.a.contains_key(k) {
+                difference += *v as f32;
+            }
+        }
+        if similarity + difference == 0.0 {
+            // both empty
+            return 1.0;
+        }
+        similarity / (similarity + difference)
+    }
+
+    pub fn new(attributes : Vec<(AttributeType, u32)>) -> Attributes {
+        let mut x : HashMap<AttributeType, u32> = HashMap::new();
+        for (k,v) in attributes {
+            if v != 0 {
+                x.insert(k, v);
+            }
+        }
+        Attributes::check_length_width(&mut x);
+        Attributes {
+            a : x,
+        }
+    }
+
+    // Length is always the longer side
+    fn check_length_width(x : &mut HashMap<AttributeType, u32>) {
+        if let Some(&w) = x.get(&AttributeType::Width) {
+            if let Some(&l) = x.get(&AttributeType::Length) {
+                if l < w {
+                    x.insert(AttributeType::Length, w);
+                    x.insert(AttributeType::Width, l);
+                }
+            } else {
+                x.insert(AttributeType::Length, w);
+                x.remove(&AttributeType::Width);
+            }
+        }
+    }
+
+    // a 0 in `but` changes nothing, the old value is kept
+    pub fn same_as_but(&self, but : Vec<(AttributeType,u32)>) -> Attributes {
+        let mut ret = Attributes{
+            a : HashMap::new(),
+        };
+        for (k,v) in but {
+            if v != 0 {
+                ret.a.insert(k,v);
+            }
+        }
+        for (k,v) in self.a.iter() {
+            if *v != 0 && ! ret.a.contains_key(k) {
+                ret.a.insert(*k,*v);
+            }
+        }
+        Attributes::check_length_width(&mut ret.a);
+        ret
+    }
+
+    // like same_as_but, but an attribute set to 0 is removed
+    pub fn with_values(&self, values : Vec<(AttributeType,u32)>) -> Attributes {
+        let mut ret = Attributes{
+            a : self.a.clone(),
+        };
+        for (k,v) in values {
+            if v != 0 {
+                ret.a.insert(k,v);
+            } else {
+                ret.a.remove(&k);
+            }
+        }
+        Attributes::check_length_width(&mut ret.a);
+        ret
+    }
+
+    pub fn get(&self, t : AttributeType) -> u32 {
+        match self.a.get(&t) {
+            Some(x) => *x,
+            None => 0,
+        }
+    }
+
+    // every attribute, in the order of AttributeType::ALL
+    pub fn to_vector(&self) -> AttributeVector {
+        let mut v = [0; index::DIMENSIONS];
+        for (i, t) in AttributeType::ALL.iter().enumerate() {
+            v[i] = self.get(*t);
+        }
+        v
+    }
+
+    // only the nonzero attributes, in no particular order
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(AttributeType, u32)> + 'a> {
+        Box::new(self.a.iter().map(|(k, v)| (*k, *v)))
+    }
+}
+
+#[derive(Hash,Eq,PartialEq,Copy,Clone,Debug,Serialize,Deserialize)]
+pub enum AttributeType {
+    Mass, Flammability, Length, Width, Hardness, Starch, Conductivity,
+}
+
+impl AttributeType {
+    pub const ALL : [AttributeType; 7] = [
+        AttributeType::Mass, AttributeType::Flammability, AttributeType::Length, AttributeType::Width,
+        AttributeType::Hardness, AttributeType::Starch, AttributeType::Conductivity,
+    ];
+
+    // as written in recipe files, eg: "Hardness"
+    pub fn from_name(name : &str) -> Option<AttributeType> {
+        AttributeType::ALL.iter().find(|t| format!("{:?}", t) == name).cloned()
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,Serialize,Deserialize)]
+pub enum Process {
+    Flatten, Coat, Heat, Cut, Dissolve,
+    // two inputs into one alloy or composite
+    Combine,
+}
+
+fn concat(v : Vec<&str>) -> String {
+    let mut s = String::new();
+    for x in v {
+        s.push_str(x);
+    }
+    s
+}
+
+// anything at least this hard won't dissolve
+const DISSOLVES_BELOW_HARDNESS : u32 = 5;
+
+impl Process {
+    pub const ALL : [Process; 6] = [
+        Process::Flatten, Process::Coat, Process::Heat, Process::Cut, Process::Dissolve, Process::Combine,
+    ];
+
+    // how many blueprints go in
+    pub fn arity(&self) -> usize {
+        match self {
+            &Process::Combine => 2,
+            _ => 1,
+        }
+    }
+
+    // the names and attributes the ideal results would have. inputs are (name, attributes)
+    pub fn apply(&self, inputs : &[(&str, &Attributes)]) -> Result<Vec<(String, Attributes)>, CraftError> {
+        if inputs.len() != self.arity() {
+            return Err(CraftError::WrongInputCount(*self, inputs.len()));
+        }
+        match self {
+            &Process::Combine => Ok(vec![Process::combine(inputs[0], inputs[1])]),
+            &Process::Cut => Process::cut(inputs[0].1, inputs[0].0),
+            &Process::Dissolve => {
+                if inputs[0].1.get(AttributeType::Hardness) >= DISSOLVES_BELOW_HARDNESS {
+                    return Err(CraftError::NotApplicable(*self));
+                }
+                Ok(vec![self.apply_to_one(inputs[0].1, inputs[0].0)])
+            },
+            _ => Ok(vec![self.apply_to_one(inputs[0].1, inputs[0].0)]),
+        }
+    }
+
+    fn apply_to_one(&self, input : &Attributes, bp_name : &str) -> (String, Attributes) {
+        use self::AttributeType::*;
+        match self {
+            &Process::Flatten => {
+                if input.get(Hardness) >= 9 {
+                    let side = (input.calc_area() as f32).sqrt() as u32;
+                    (
+                        concat(vec!["crushed ", bp_name]),
+                        Attributes::new(vec![(Mass,input.get(Mass)), (Length,side), (Width,side), (Starch,input.get(Starch))])
+                    )
+                } else {
+                    (
+                        concat(vec!["flattened ", bp_name]),
+                        input.same_as_but(vec![(Width, input.get(Width)/2), (Hardness, input.get(Hardness)+5)]),
+                    )
+                }
+
+            }
+            &Process::Coat => {
+                (
+                    concat(vec!["coated ", bp_name]),
+                    input.same_as_but(vec![(Conductivity, input.get(Conductivity)/3)]),
+                )
+            }
+            &Process::Heat => {
+                if input.get(Flammability) > 0 {
+                    // burns away whatever could burn
+                    (
+                        concat(vec!["charred ", bp_name]),
+                        input.with_values(vec![
+                            (Mass, input.get(Mass)*2/3), (Flammability, 0), (Starch, 0), (Hardness, input.get(Hardness)+1),
+                        ]),
+                    )
+                } else {
+                    // softens
+                    (
+                        concat(vec!["heated ", bp_name]),
+                        input.same_as_but(vec![(Hardness, input.get(Hardness)*2/3)]),
+                    )
+                }
+            }
+            &Process::Dissolve => {
+                // loses all shape
+                (
+                    concat(vec!["dissolved ", bp_name]),
+                    input.with_values(vec![(Length, 0), (Width, 0), (Hardness, 0)]),
+                )
+            }
+            &Process::Cut | &Process::Combine => unreachable!(),
+        }
+    }
+
+    // two pieces, splitting the length and the mass between them
+    fn cut(input : &Attributes, bp_name : &str) -> Result<Vec<(String, Attributes)>, CraftError> {
+        use self::AttributeType::*;
+        let length = input.get(Length);
+        if length < 2 {
+            return Err(CraftError::NotApplicable(Process::Cut));
+        }
+        let name = concat(vec!["cut ", bp_name]);
+        let short = length / 2;
+        // whatever rounding takes off the short piece goes to the long one
+        let short_mass = input.get(Mass) * short / length;
+        Ok([(short, short_mass), (length - short, input.get(Mass) - short_mass)].iter().map(|&(l, mass)| {
+            (name.clone(), input.with_values(vec![(Length, l), (Mass, mass)]))
+        }).collect())
+    }
+
+    // conductive things fuse into an alloy. anything else makes a composite
+    fn combine(a : (&str, &Attributes), b : (&str, &Attributes)) -> (String, Attributes) {
+        use self::AttributeType::*;
+        let ((a_name, a), (b_name, b)) = (a, b);
+        let alloy = a.get(Conductivity) > 0 && b.get(Conductivity) > 0;
+        let avg = |t| (a.get(t) + b.get(t)) / 2;
+        let max = |t| ::std::cmp::max(a.get(t), b.get(t));
+        let hardness = if alloy {max(Hardness) + 1} else {avg(Hardness)};
+        (
+            concat(vec![a_name, "-", b_name, if alloy {" alloy"} else {" composite"}]),
+            Attributes::new(vec![
+                (Mass, a.get(Mass) + b.get(Mass)),
+                (Length, max(Length)),
+                (Width, max(Width)),
+                (Hardness, hardness),
+                (Flammability, avg(Flammability)),
+                (Starch, a.get(Starch) + b.get(Starch)),
+                (Conductivity, avg(Conductivity)),
+            ]),
+        )
+    }
+}
+
+// results at least this similar to a known blueprint ARE that blueprint
+pub const DEFAULT_SIMILARITY_THRESHOLD : f32 = 0.9;
+
+// bump whenever SavedPool (or anything inside it) changes shape
+pub const POOL_SCHEMA_VERSION : u32 = 1;
+
+// recipes and the similarity function are not saved; they come from code and data files
+#[derive(Serialize,Deserialize)]
+struct SavedPool {
+    version : u32,
+    next_id : BlueprintID,
+    threshold : f32,
+    // sorted by ID
+    blueprints : Vec<(BlueprintID, Blueprint)>,
+}
+
+#[derive(Debug)]
+pub struct BlueprintPool {
+    bps : HashMap<BlueprintID, Blueprint>,
+    next_id : BlueprintID,
+    recipes : Vec<Recipe>,
+    similarity : Box<Similarity>,
+    threshold : f32,
+    index : AttributeIndex,
+}
+
+impl BlueprintPool {
+    pub fn new() -> BlueprintPool {
+        BlueprintPool{
+            bps : HashMap::new(),
+            next_id : 0,
+            recipes : vec![],
+            similarity : Box::new(Overlap),
+            threshold : DEFAULT_SIMILARITY_THRESHOLD,
+            index : AttributeIndex::new(),
+        }
+    }
+
+    pub fn with_similarity(mut self, similarity : Box<Similarity>, threshold : f32) -> BlueprintPool {
+        self.similarity = similarity;
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn get_threshold(&self) -> f32 {self.threshold}
+
+    pub fn set_threshold(&mut self, threshold : f32) {
+        self.threshold = threshold;
+    }
+
+    pub fn get(&self, bp_id : BlueprintID) -> Option<&Blueprint> {
+        self.bps.get(&bp_id)
+    }
+
+    pub fn len(&self) -> usize {self.bps.len()}
+
+    // replaces any recipes loaded before. returns how many were loaded
+    pub fn load_recipes<R: BufRead>(&mut self, r : R) -> Result<usize, RecipeError> {
+        self.recipes = read_recipes(r)?;
+        Ok(self.recipes.len())
+    }
+
+    pub fn get_recipes(&self) -> &[Recipe] {&self.recipes}
+
+    pub fn get_recipe(&self, name : &str) -> Option<&Recipe> {
+        self.recipes.iter().find(|r| r.get_name() == name)
+    }
+
+    // sorted
+    pub fn ids(&self) -> Vec<BlueprintID> {
+        let mut v: Vec<BlueprintID> = self.bps.keys().cloned().collect();
+        v.sort();
+        v
+    }
+
+    // the MOST similar blueprint above the threshold. ties go to the lowest ID
+    pub fn find_similar(&self, att : &Attributes) -> Option<BlueprintID> {
+        self.find_most_similar(att).map(|(bp_id, _)| bp_id)
+    }
+
+    pub fn find_most_similar(&self, att : &Attributes) -> Option<(BlueprintID, f32)> {
+        let candidates = match self.similarity.l1_bound(att, self.threshold) {
+            Some(bound) => self.index.within_l1(&att.to_vector(), bound),
+            None => self.bps.keys().cloned().collect(),
+        };
+        let mut best : Option<(BlueprintID, f32)> = None;
+        for bp_id in candidates {
+            let sim = self.similarity.similarity(att, &self.bps[&bp_id].attributes);
+            if sim <= self.threshold {continue}
+            best = match best {
+                Some((best_id, best_sim)) if best_sim > sim || (best_sim == sim && best_id < bp_id) => best,
+                _ => Some((bp_id, sim)),
+            };
+        }
+        best
+    }
+
+    pub fn invent(&mut self, bp : Blueprint) -> BlueprintID {
+        let bp_id = self.next_id;
+        self.next_id += 1;
+        self.index.insert(bp.attributes.to_vector(), bp_id);
+        self.bps.insert(bp_id, bp);
+        bp_id
+    }
+
+    // for each result, an existing blueprint if one is similar enough, otherwise a new one.
+    // `inputs` must have as many blueprints as the process takes
+    pub fn apply_process(&mut self, inputs : &[BlueprintID], p : Process) -> Result<Vec<BlueprintID>, CraftError> {
+        let results = {
+            let mut named = vec![];
+            for bp_id in inputs.iter() {
+                match self.bps.get(bp_id) {
+                    Some(bp) => named.push((&bp.name[..], &bp.attributes)),
+                    None => return Err(CraftError::UnknownBlueprint(*bp_id)),
+                }
+            }
+            p.apply(&named)?
+        };
+        Ok(results.into_iter().map(|(new_name, ideal_atts)| self.find_or_invent(new_name, ideal_atts)).collect())
+    }
+
+    pub fn apply_recipe(&mut self, bp_id : BlueprintID, recipe_name : &str) -> Result<BlueprintID, CraftError> {
+        let (new_name, ideal_atts) = {
+            let recipe = self.get_recipe(recipe_name).ok_or(CraftError::UnknownRecipe(recipe_name.to_owned()))?;
+            let bp = self.bps.get(&bp_id).ok_or(CraftError::UnknownBlueprint(bp_id))?;
+            recipe.apply(&bp.attributes, &bp.name).ok_or(CraftError::RecipeDoesNotApply(recipe_name.to_owned()))?
+        };
+        Ok(self.find_or_invent(new_name, ideal_atts))
+    }
+
+    // the similar blueprint the pool already knows, or a new one
+    pub fn find_or_invent(&mut self, name : String, attributes : Attributes) -> BlueprintID {
+        match self.find_similar(&attributes) {
+            Some(similar_bp_id) => similar_bp_id,
+            None => self.invent(Blueprint {
+                name : name,
+                attributes : attributes,
+            }),
+        }
+    }
+
+    pub fn save<W: Write>(&self, writer : W) -> Result<(), CraftError> {
+        let saved = SavedPool {
+            version : POOL_SCHEMA_VERSION,
+            next_id : self.next_id,
+            threshold : self.threshold,
+            blueprints : self.ids().into_iter().map(|id| (id, self.bps[&id].clone())).collect(),
+        };
+        serde_json::to_writer(writer, &saved)?;
+        Ok(())
+    }
+
+    // IDs are kept as they were, and new ones continue where the saved pool left off.
+    // uses the default similarity; see with_similarity
+    pub fn load<R: Read>(reader : R) -> Result<BlueprintPool, CraftError> {
+        let raw : serde_json::Value = serde_json::from_reader(reader)?;
+        // checked before anything else, as other versions may look nothing like this one
+        match raw.get("version").and_then(|v| v.as_u64()) {
+            Some(v) if v == POOL_SCHEMA_VERSION as u64 => (),
+            Some(v) => return Err(CraftError::UnsupportedVersion(v as u32)),
+            None => return Err(CraftError::UnsupportedVersion(0)),
+        }
+        let saved : SavedPool = serde_json::from_value(raw)?;
+        let mut pool = BlueprintPool::new();
+        pool.threshold = saved.threshold;
+        for (id, bp) in saved.blueprints {
+            pool.index.insert(bp.attributes.to_vector(), id);
+            pool.bps.insert(id, bp);
+            pool.next_id = ::std::cmp::max(pool.next_id, id + 1);
+        }
+        pool.next_id = ::std::cmp::max(pool.next_id, saved.next_id);
+        Ok(pool)
+    }
+
+    // brings in every blueprint of `other` that this pool has nothing similar to, under a
+    // new ID. returns what each of `other`'s IDs is called here, to rewrite references with
+    pub fn merge(&mut self, other : &BlueprintPool) -> HashMap<BlueprintID, BlueprintID> {
+        other.ids().into_iter().map(|id| {
+            let bp = &other.bps[&id];
+            (id, self.find_or_invent(bp.name.clone(), bp.attributes.clone()))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::AttributeType::*;
+
+    fn copper() -> Attributes {
+        Attributes::new(vec![(Mass, 10), (Length, 8), (Width, 6), (Hardness, 2), (Conductivity, 9)])
+    }
+
+    fn apply_one(p: Process, name: &str, atts: &Attributes) -> (String, Attributes) {
+        let mut results = p.apply(&[(name, atts)]).unwrap();
+        assert_eq!(results.len(), 1);
+        results.pop().unwrap()
+    }
+
+    #[test]
+    fn flatten_halves_the_width_and_hardens() {
+        let (name, atts) = apply_one(Process::Flatten, "copper", &copper());
+        assert_eq!(name, "flattened copper");
+        assert_eq!(atts, Attributes::new(vec![(Mass, 10), (Length, 8), (Width, 3), (Hardness, 7), (Conductivity, 9)]));
+    }
+
+    #[test]
+    fn flatten_crushes_hard_things() {
+        let granite = Attributes::new(vec![(Mass, 50), (Length, 8), (Width, 2), (Hardness, 9), (Starch, 1), (Flammability, 3), (Conductivity, 4)]);
+        let (name, atts) = apply_one(Process::Flatten, "granite", &granite);
+        assert_eq!(name, "crushed granite");
+        // a square as long as the original, keeping only mass and starch
+        assert_eq!(atts, Attributes::new(vec![(Mass, 50), (Length, 8), (Width, 8), (Starch, 1)]));
+    }
+
+    #[test]
+    fn coat_cuts_conductivity_to_a_third() {
+        let (name, atts) = apply_one(Process::Coat, "copper", &copper());
+        assert_eq!(name, "coated copper");
+        assert_eq!(atts, copper().same_as_but(vec![(Conductivity, 3)]));
+        // a third of 2 is 0, which leaves it as it was
+        let wire = Attributes::new(vec![(Length, 4), (Conductivity, 2)]);
+        let (_, atts) = apply_one(Process::Coat, "wire", &wire);
+        assert_eq!(atts, wire);
+    }
+
+    fn wood() -> Attributes {
+        Attributes::new(vec![(Mass, 9), (Length, 5), (Width, 2), (Hardness, 1), (Flammability, 4), (Starch, 3)])
+    }
+
+    #[test]
+    fn heat_chars_what_can_burn() {
+        let (name, atts) = apply_one(Process::Heat, "wood", &wood());
+        assert_eq!(name, "charred wood");
+        // the flammability and starch burn away completely
+        assert_eq!(atts, Attributes::new(vec![(Mass, 6), (Length, 5), (Width, 2), (Hardness, 2)]));
+    }
+
+    #[test]
+    fn heat_softens_what_cannot_burn() {
+        let (name, atts) = apply_one(Process::Heat, "copper", &copper());
+        assert_eq!(name, "heated copper");
+        assert_eq!(atts, copper().same_as_but(vec![(Hardness, 1)]));
+        // there is no softening it any further
+        let (_, again) = apply_one(Process::Heat, "copper", &atts);
+        assert_eq!(again, atts);
+    }
+
+    #[test]
+    fn cut_keeps_the_mass_and_length() {
+        for mass in 0..20 {
+            for length in 2..12 {
+                let rod = Attributes::new(vec![(Mass, mass), (Length, length), (Width, 1), (Conductivity, 3)]);
+                let pieces = Process::Cut.apply(&[("rod", &rod)]).unwrap();
+                assert_eq!(pieces.len(), 2);
+                assert!(pieces.iter().all(|&(ref name, _)| name == "cut rod"));
+                assert_eq!(pieces.iter().map(|p| p.1.get(Mass)).sum::<u32>(), mass, "{:?}", pieces);
+                assert_eq!(pieces.iter().map(|p| p.1.get(Length)).sum::<u32>(), length, "{:?}", pieces);
+                assert!(pieces.iter().all(|p| p.1.get(Conductivity) == 3 && p.1.get(Width) == 1));
+            }
+        }
+        let stub = Attributes::new(vec![(Mass, 4), (Length, 1)]);
+        match Process::Cut.apply(&[("stub", &stub)]) {
+            Err(CraftError::NotApplicable(Process::Cut)) => (),
+            other => panic!("expected cutting a stub to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dissolve_loses_all_shape() {
+        let (name, atts) = apply_one(Process::Dissolve, "copper", &copper());
+        assert_eq!(name, "dissolved copper");
+        assert_eq!(atts, Attributes::new(vec![(Mass, 10), (Conductivity, 9)]));
+        let hard = copper().same_as_but(vec![(Hardness, DISSOLVES_BELOW_HARDNESS)]);
+        match Process::Dissolve.apply(&[("steel", &hard)]) {
+            Err(CraftError::NotApplicable(Process::Dissolve)) => (),
+            other => panic!("expected hard things not to dissolve, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn combine_takes_two() {
+        let (copper, wood) = (copper(), wood());
+        match Process::Combine.apply(&[("copper", &copper)]) {
+            Err(CraftError::WrongInputCount(Process::Combine, 1)) => (),
+            other => panic!("expected the wrong input count, got {:?}", other),
+        }
+        match Process::Heat.apply(&[("copper", &copper), ("wood", &wood)]) {
+            Err(CraftError::WrongInputCount(Process::Heat, 2)) => (),
+            other => panic!("expected the wrong input count, got {:?}", other),
+        }
+        let alloy = Process::Combine.apply(&[("copper", &copper), ("copper", &copper)]).unwrap();
+        assert_eq!(alloy[0].0, "copper-copper alloy");
+        assert_eq!(alloy[0].1.get(Hardness), 3);
+        let composite = Process::Combine.apply(&[("copper", &copper), ("wood", &wood)]).unwrap();
+        assert_eq!(composite[0].0, "copper-wood composite");
+        assert_eq!(composite[0].1.get(Mass), 19);
+    }
+
+    #[test]
+    fn same_as_but_replaces_and_adds() {
+        // a 0 keeps the old value
+        let atts = copper().same_as_but(vec![(Mass, 40), (Starch, 2), (Hardness, 0)]);
+        assert_eq!(atts, Attributes::new(vec![(Mass, 40), (Length, 8), (Width, 6), (Hardness, 2), (Starch, 2), (Conductivity, 9)]));
+        assert_eq!(copper().same_as_but(vec![]), copper());
+    }
+
+    #[test]
+    fn with_values_replaces_adds_and_removes() {
+        let atts = copper().with_values(vec![(Mass, 40), (Starch, 2), (Hardness, 0)]);
+        assert_eq!(atts, Attributes::new(vec![(Mass, 40), (Length, 8), (Width, 6), (Starch, 2), (Conductivity, 9)]));
+        // a zero isn't stored, so equality doesn't depend on how an attribute came to be 0
+        assert!(atts.iter().all(|(t, _)| t != Hardness));
+        // without a length, the width becomes it
+        let atts = copper().with_values(vec![(Length, 0)]);
+        assert_eq!((atts.get(Length), atts.get(Width)), (6, 0));
+        assert_eq!(copper().with_values(vec![]), copper());
+    }
+
+    #[test]
+    fn length_is_the_longer_side() {
+        let swapped = Attributes::new(vec![(Length, 2), (Width, 5)]);
+        assert_eq!((swapped.get(Length), swapped.get(Width)), (5, 2));
+        let only_width = Attributes::new(vec![(Width, 5)]);
+        assert_eq!((only_width.get(Length), only_width.get(Width)), (5, 0));
+        // also after changing one side
+        let widened = Attributes::new(vec![(Length, 4), (Width, 3)]).same_as_but(vec![(Width, 7)]);
+        assert_eq!((widened.get(Length), widened.get(Width)), (7, 4));
+
+        let mut raw = HashMap::new();
+        raw.insert(Length, 3);
+        raw.insert(Width, 3);
+        Attributes::check_length_width(&mut raw);
+        assert_eq!((raw[&Length], raw[&Width]), (3, 3));
+    }
+
+    #[test]
+    fn density_without_area_is_zero() {
+        assert_eq!(Attributes::new(vec![(Mass, 100)]).density(), 0);
+        assert_eq!(Attributes::new(vec![]).density(), 0);
+        assert_eq!(Attributes::new(vec![(Mass, 100), (Length, 5)]).density(), 16);
+    }
+
+    #[test]
+    fn similarity_of_nothing_to_nothing_is_one() {
+        let empty = Attributes::new(vec![]);
+        assert_eq!(empty.similarity_coefficient(&empty), 1.0);
+        assert_eq!(empty.similarity_coefficient(&copper()), 0.0);
+        assert_eq!(copper().similarity_coefficient(&copper()), 1.0);
+    }
+
+    #[test]
+    fn similar_results_reuse_blueprints() {
+        let mut pool = BlueprintPool::new();
+        let bp_id = pool.invent(Blueprint::new("copper".to_owned(), copper()));
+        assert_eq!(pool.find_similar(&copper()), Some(bp_id));
+        assert_eq!(pool.find_similar(&copper().same_as_but(vec![(Mass, 9)])), Some(bp_id));
+        assert_eq!(pool.find_similar(&Attributes::new(vec![(Starch, 40)])), None);
+
+        // close enough to copper, so it is copper
+        let nearly = pool.find_or_invent("nearly copper".to_owned(), copper().same_as_but(vec![(Mass, 8)]));
+        assert_eq!((nearly, pool.len()), (bp_id, 1));
+
+        // flattening changes too much, so it is something new
+        let flattened = pool.apply_process(&[bp_id], Process::Flatten).unwrap();
+        assert_eq!(flattened.len(), 1);
+        assert!(flattened[0] != bp_id);
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.get(flattened[0]).unwrap().get_name(), "flattened copper");
+        // and the second time round, it is the one just invented
+        assert_eq!(pool.apply_process(&[bp_id], Process::Flatten).unwrap(), flattened);
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn unknown_blueprints_are_an_error() {
+        let mut pool = BlueprintPool::new();
+        let bp_id = pool.invent(Blueprint::new("copper".to_owned(), copper()));
+        match pool.apply_process(&[bp_id, 42], Process::Combine) {
+            Err(CraftError::UnknownBlueprint(42)) => (),
+            other => panic!("expected an unknown blueprint, got {:?}", other),
+        }
+        pool.load_recipes("{\"name\": \"coat\", \"prefix\": \"coated \", \"set\": {}}".as_bytes()).unwrap();
+        match pool.apply_recipe(42, "coat") {
+            Err(CraftError::UnknownBlueprint(42)) => (),
+            other => panic!("expected an unknown blueprint, got {:?}", other),
+        }
+        assert_eq!(pool.len(), 1);
+    }
+
+    fn pool() -> BlueprintPool {
+        let mut pool = BlueprintPool::new();
+        pool.invent(Blueprint::new("copper".to_owned(), copper()));
+        pool.invent(Blueprint::new("wood".to_owned(), wood()));
+        pool
+    }
+
+    fn saved(pool: &BlueprintPool) -> Vec<u8> {
+        let mut out = vec![];
+        pool.save(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn pools_round_trip() {
+        let mut pool = pool();
+        pool.set_threshold(0.8);
+        let mut loaded = BlueprintPool::load(&saved(&pool)[..]).unwrap();
+        assert_eq!(loaded.ids(), vec![0, 1]);
+        for id in pool.ids() {
+            assert_eq!(loaded.get(id), pool.get(id));
+        }
+        assert_eq!(loaded.get_threshold(), 0.8);
+        // attributes are a map, so only the same once parsed
+        let parsed = |p: &BlueprintPool| serde_json::from_slice::<serde_json::Value>(&saved(p)).unwrap();
+        assert_eq!(parsed(&loaded), parsed(&pool));
+        // the loaded index finds things too
+        assert_eq!(loaded.find_similar(&copper()), Some(0));
+        // new IDs carry on after the saved ones
+        assert_eq!(loaded.invent(Blueprint::new("starch".to_owned(), Attributes::new(vec![(Starch, 40)]))), 2);
+
+        // even past IDs that are no longer in the pool
+        let mut raw: serde_json::Value = serde_json::from_slice(&saved(&pool)).unwrap();
+        raw["next_id"] = serde_json::Value::from(10);
+        let mut loaded = BlueprintPool::load(raw.to_string().as_bytes()).unwrap();
+        assert_eq!(loaded.invent(Blueprint::new("starch".to_owned(), Attributes::new(vec![(Starch, 40)]))), 10);
+    }
+
+    #[test]
+    fn pools_from_other_versions_are_refused() {
+        let mut raw: serde_json::Value = serde_json::from_slice(&saved(&pool())).unwrap();
+        raw["version"] = serde_json::Value::from(POOL_SCHEMA_VERSION + 1);
+        match BlueprintPool::load(raw.to_string().as_bytes()) {
+            Err(CraftError::UnsupportedVersion(v)) if v == POOL_SCHEMA_VERSION + 1 => (),
+            other => panic!("expected an unsupported version, got {:?}", other.map(|p| p.ids())),
+        }
+        raw.as_object_mut().unwrap().remove("version");
+        match BlueprintPool::load(raw.to_string().as_bytes()) {
+            Err(CraftError::UnsupportedVersion(0)) => (),
+            other => panic!("expected an unsupported version, got {:?}", other.map(|p| p.ids())),
+        }
+    }
+
+    #[test]
+    fn merging_maps_near_duplicates_onto_what_is_there() {
+        let mut pool = pool();
+        let mut other = BlueprintPool::new();
+        let stone = Attributes::new(vec![(Mass, 12), (Length, 3), (Width, 3), (Hardness, 7)]);
+        other.invent(Blueprint::new("stone".to_owned(), stone.clone()));
+        other.invent(Blueprint::new("nearly copper".to_owned(), copper().same_as_but(vec![(Mass, 9)])));
+        other.invent(Blueprint::new("wood".to_owned(), wood()));
+
+        let mapping = pool.merge(&other);
+        let expected: HashMap<BlueprintID, BlueprintID> = vec![(0, 2), (1, 0), (2, 1)].into_iter().collect();
+        assert_eq!(mapping, expected);
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.get(2), Some(&Blueprint::new("stone".to_owned(), stone)));
+        // merging again brings in nothing new
+        assert_eq!(pool.merge(&other), expected);
+        assert_eq!(pool.len(), 3);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::io::{self,BufRead};
+use ::serde_json;
+use super::{Attributes,AttributeType};
+
+/*
+Processes that designers define as data instead of code. a recipe file has one
+JSON recipe per line (blank lines are skipped):
+
+{"name": "temper", "prefix": "tempered ",
+ "conditions": [{"attribute": "Hardness", "min": 3, "max": 8}],
+ "set": {"Hardness": "Hardness + 4", "Width": "Width / 2"}}
+
+(on a single line). a recipe only applies if every condition holds. each
+formula in `set` is computed from the INPUT's attributes; attributes not in
+`set` are kept as they are, and a formula that comes to 0 removes its
+attribute. formulas are integer arithmetic over attribute names and numbers:
++ - * / and parentheses. subtraction stops at 0, and dividing by 0 gives 0.
+*/
+
+#[derive(Debug)]
+pub enum RecipeError {
+    Io(io::Error),
+    // lines count from 1
+    Invalid(usize, RecipeProblem),
+}
+
+#[derive(Debug)]
+pub enum RecipeProblem {
+    Json(serde_json::Error),
+    UnknownAttribute(String),
+    // the formula, and what is wrong with it
+    BadFormula(String, String),
+    DuplicateName(String),
+    // min above max
+    ImpossibleCondition(AttributeType),
+}
+
+impl From<io::Error> for RecipeError {
+    fn from(e: io::Error) -> RecipeError {RecipeError::Io(e)}
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCondition {
+    attribute: String,
+    min: Option<u32>,
+    max: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRecipe {
+    name: String,
+    prefix: String,
+    #[serde(default)]
+    conditions: Vec<RawCondition>,
+    #[serde(default)]
+    set: BTreeMap<String, String>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Condition {
+    attribute: AttributeType,
+    min: Option<u32>,
+    max: Option<u32>,
+}
+
+impl Condition {
+    fn holds(&self, atts: &Attributes) -> bool {
+        let v = atts.get(self.attribute);
+        self.min.map(|m| v >= m).unwrap_or(true) && self.max.map(|m| v <= m).unwrap_or(true)
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Op {
+    Add, Sub, Mul, Div,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Formula {
+    Number(u32),
+    Attribute(AttributeType),
+    Apply(Op, Box<Formula>, Box<Formula>),
+}
+
+impl Formula {
+    pub fn evaluate(&self, atts: &Attributes) -> u32 {
+        match self {
+            &Formula::Number(n) => n,
+            &Formula::Attribute(t) => atts.get(t),
+            &Formula::Apply(op, ref a, ref b) => {
+                let (a, b) = (a.evaluate(atts), b.evaluate(atts));
+                match op {
+                    Op::Add => a.saturating_add(b),
+                    Op::Sub => a.saturating_sub(b),
+                    Op::Mul => a.saturating_mul(b),
+                    Op::Div => if b == 0 {0} else {a / b},
+                }
+            },
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Formula, RecipeProblem> {
+        let tokens = tokenize(text)?;
+        let mut at = 0;
+        let f = parse_sum(text, &tokens, &mut at)?;
+        if at != tokens.len() {
+            return Err(bad_formula(text, "unexpected input after the end"));
+        }
+        Ok(f)
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum Token {
+    Number(u32),
+    Name(String),
+    Symbol(char),
+}
+
+fn bad_formula(text: &str, why: &str) -> RecipeProblem {
+    RecipeProblem::BadFormula(text.to_owned(), why.to_owned())
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, RecipeProblem> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_digit(10) {
+            let mut n: u32 = 0;
+            while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                n = n.checked_mul(10).and_then(|n| n.checked_add(d))
+                    .ok_or(bad_formula(text, "number too large"))?;
+                chars.next();
+            }
+            tokens.push(Token::Number(n));
+        } else if c.is_alphabetic() {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !c.is_alphanumeric() {break}
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Name(name));
+        } else if "+-*/()".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            return Err(bad_formula(text, &format!("unexpected character {:?}", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+// sum := product (('+'|'-') product)*
+fn parse_sum(text: &str, tokens: &[Token], at: &mut usize) -> Result<Formula, RecipeProblem> {
+    let mut f = parse_product(text, tokens, at)?;
+    loop {
+        let op = match tokens.get(*at) {
+            Some(&Token::Symbol('+')) => Op::Add,
+            Some(&Token::Symbol('-')) => Op::Sub,
+            _ => return Ok(f),
+        };
+        *at += 1;
+        f = Formula::Apply(op, Box::new(f), Box::new(parse_product(text, tokens, at)?));
+    }
+}
+
+// product := term (('*'|'/') term)*
+fn parse_product(text: &str, tokens: &[Token], at: &mut usize) -> Result<Formula, RecipeProblem> {
+    let mut f = parse_term(text, tokens, at)?;
+    loop {
+        let op = match tokens.get(*at) {
+            Some(&Token::Symbol('*')) => Op::Mul,
+            Some(&Token::Symbol('/')) => Op::Div,
+            _ => return Ok(f),
+        };
+        *at += 1;
+        f = Formula::Apply(op, Box::new(f), Box::new(parse_term(text, tokens, at)?));
+    }
+}
+
+// term := number | attribute | '(' sum ')'
+fn parse_term(text: &str, tokens: &[Token], at: &mut usize) -> Result<Formula, RecipeProblem> {
+    let token = tokens.get(*at).cloned();
+    *at += 1;
+    match token {
+        Some(Token::Number(n)) => Ok(Formula::Number(n)),
+        Some(Token::Name(name)) => match AttributeType::from_name(&name) {
+            Some(t) => Ok(Formula::Attribute(t)),
+            None => Err(RecipeProblem::UnknownAttribute(name)),
+        },
+        Some(Token::Symbol('(')) => {
+            let f = parse_sum(text, tokens, at)?;
+            match tokens.get(*at) {
+                Some(&Token::Symbol(')')) => {
+                    *at += 1;
+                    Ok(f)
+                },
+                _ => Err(bad_formula(text, "missing )")),
+            }
+        },
+        Some(_) => Err(bad_formula(text, "expected a number, an attribute or (")),
+        None => Err(bad_formula(text, "ends too early")),
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Recipe {
+    name: String,
+    prefix: String,
+    conditions: Vec<Condition>,
+    // sorted by attribute name
+    formulas: Vec<(AttributeType, Formula)>,
+}
+
+impl Recipe {
+    pub fn get_name(&self) -> &str {&self.name}
+    pub fn get_prefix(&self) -> &str {&self.prefix}
+
+    pub fn applies_to(&self, atts: &Attributes) -> bool {
+        self.conditions.iter().all(|c| c.holds(atts))
+    }
+
+    // None if the conditions don't hold
+    pub fn apply(&self, input: &Attributes, bp_name: &str) -> Option<(String, Attributes)> {
+        if !self.applies_to(input) {return None}
+        let changes = self.formulas.iter()
+            .map(|&(t, ref f)| (t, f.evaluate(input)))
+            .collect();
+        Some((format!("{}{}", self.prefix, bp_name), input.with_values(changes)))
+    }
+
+    fn from_raw(raw: RawRecipe) -> Result<Recipe, RecipeProblem> {
+        let attribute = |name: &str| AttributeType::from_name(name)
+            .ok_or(RecipeProblem::UnknownAttribute(name.to_owned()));
+        let mut conditions = vec![];
+        for c in raw.conditions.iter() {
+            let t = attribute(&c.attribute)?;
+            if let (Some(min), Some(max)) = (c.min, c.max) {
+                if min > max {
+                    return Err(RecipeProblem::ImpossibleCondition(t));
+                }
+            }
+            conditions.push(Condition {attribute: t, min: c.min, max: c.max});
+        }
+        let mut formulas = vec![];
+        for (name, text) in raw.set.iter() {
+            formulas.push((attribute(name)?, Formula::parse(text)?));
+        }
+        Ok(Recipe {
+            name: raw.name,
+            prefix: raw.prefix,
+            conditions: conditions,
+            formulas: formulas,
+        })
+    }
+}
+
+// every recipe in the file, or the first problem found
+pub fn read_recipes<R: BufRead>(r: R) -> Result<Vec<Recipe>, RecipeError> {
+    let mut recipes: Vec<Recipe> = vec![];
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {continue}
+        let invalid = |p| RecipeError::Invalid(i+1, p);
+        let raw: RawRecipe = serde_json::from_str(&line)
+            .map_err(|e| invalid(RecipeProblem::Json(e)))?;
+        if recipes.iter().any(|r| r.name == raw.name) {
+            return Err(invalid(RecipeProblem::DuplicateName(raw.name)));
+        }
+        recipes.push(Recipe::from_raw(raw).map_err(&invalid)?);
+    }
+    Ok(recipes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Process;
+    use super::super::AttributeType::*;
+
+    // the shipped recipes that stand in for processes have to agree with them
+    #[test]
+    fn shipped_recipes_match_their_processes() {
+        let recipes = read_recipes(&include_bytes!("../../assets/recipes.jsonl")[..]).unwrap();
+        let recipe = |name: &str| recipes.iter().find(|r| r.get_name() == name).unwrap();
+        let inputs = vec![
+            Attributes::new(vec![(Mass, 10), (Length, 8), (Width, 6), (Hardness, 2), (Conductivity, 9)]),
+            Attributes::new(vec![(Mass, 50), (Length, 7), (Width, 2), (Hardness, 9), (Starch, 1), (Flammability, 3), (Conductivity, 4)]),
+            Attributes::new(vec![(Mass, 3), (Length, 1), (Hardness, 12)]),
+            Attributes::new(vec![(Starch, 5), (Width, 4)]),
+        ];
+        for input in inputs.iter() {
+            let by_process = Process::Flatten.apply(&[("stuff", input)]).unwrap().pop();
+            let by_recipe = recipe("crush").apply(input, "stuff").or_else(|| recipe("flatten").apply(input, "stuff"));
+            assert_eq!(by_recipe, by_process, "flattening {:?}", input);
+
+            let by_process = Process::Coat.apply(&[("stuff", input)]).unwrap().pop();
+            assert_eq!(recipe("coat").apply(input, "stuff"), by_process, "coating {:?}", input);
+        }
+    }
+}
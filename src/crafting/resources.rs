@@ -0,0 +1,231 @@
+use ::rand::{SeedableRng,Rng,Isaac64Rng};
+use ::world::{World,Material,MaterialThresholds,PointSampleData};
+use super::{BlueprintPool,BlueprintID,Attributes,AttributeType};
+use super::AttributeType::*;
+
+/*
+What can be gathered from the ground. every Material yields a few raw
+blueprints, whose attributes depend on:
+    the planet      each world scales each material's attributes its own way
+    the climate     cold makes things harder and less flammable, warmth grows starch
+    the altitude    higher up, things are denser
+climate and altitude are cut into BANDS bands, so that neighbouring cells
+yield the same blueprints rather than endless near-copies.
+*/
+
+const BANDS: u32 = 4;
+
+// how far above the water level counts as the highest altitude band
+const ALTITUDE_RANGE: f32 = 0.5;
+
+// how far above the snow temperature counts as the warmest band
+const WARMTH_RANGE: f32 = 0.5;
+
+// each planet scales each attribute of each material by somewhere in here
+const PLANET_VARIATION: (f32, f32) = (0.75, 1.25);
+
+struct RawResource {
+    name: &'static str,
+    attributes: &'static [(AttributeType, u32)],
+}
+
+fn raw_resources(mat: Material) -> &'static [RawResource] {
+    const ROCK: &'static [RawResource] = &[
+        RawResource {name: "stone", attributes: &[(Mass, 12), (Length, 3), (Width, 3), (Hardness, 7)]},
+        RawResource {name: "ore", attributes: &[(Mass, 14), (Length, 2), (Width, 2), (Hardness, 6), (Conductivity, 8)]},
+    ];
+    const DARK_ROCK: &'static [RawResource] = &[
+        RawResource {name: "basalt", attributes: &[(Mass, 16), (Length, 3), (Width, 3), (Hardness, 9)]},
+        RawResource {name: "ore", attributes: &[(Mass, 14), (Length, 2), (Width, 2), (Hardness, 6), (Conductivity, 8)]},
+    ];
+    const TREES: &'static [RawResource] = &[
+        RawResource {name: "wood", attributes: &[(Mass, 6), (Length, 20), (Width, 2), (Hardness, 4), (Flammability, 7), (Starch, 3)]},
+        RawResource {name: "resin", attributes: &[(Mass, 1), (Length, 1), (Flammability, 9), (Starch, 1)]},
+    ];
+    const GRASS: &'static [RawResource] = &[
+        RawResource {name: "fibre", attributes: &[(Mass, 1), (Length, 8), (Width, 1), (Flammability, 6), (Starch, 2)]},
+        RawResource {name: "seeds", attributes: &[(Mass, 1), (Length, 1), (Flammability, 2), (Starch, 8)]},
+    ];
+    const SAND: &'static [RawResource] = &[
+        RawResource {name: "sand", attributes: &[(Mass, 8), (Length, 1), (Hardness, 2)]},
+        RawResource {name: "clay", attributes: &[(Mass, 9), (Length, 2), (Width, 2), (Hardness, 1)]},
+    ];
+    const ICE: &'static [RawResource] = &[
+        RawResource {name: "ice", attributes: &[(Mass, 5), (Length, 3), (Width, 3), (Hardness, 3), (Conductivity, 1)]},
+    ];
+    const SNOW: &'static [RawResource] = &[
+        RawResource {name: "snow", attributes: &[(Mass, 2), (Length, 1), (Hardness, 1)]},
+    ];
+    const WATER: &'static [RawResource] = &[
+        RawResource {name: "water", attributes: &[(Mass, 5), (Conductivity, 2)]},
+    ];
+    match mat {
+        Material::Rock => ROCK,
+        Material::DarkRock => DARK_ROCK,
+        Material::Trees => TREES,
+        Material::Grass => GRASS,
+        Material::Sand => SAND,
+        Material::Ice => ICE,
+        Material::Snow => SNOW,
+        Material::Water => WATER,
+    }
+}
+
+// 0 .. BANDS-1
+fn band(x: f32) -> u32 {
+    let clamped = if x < 0.0 {0.0} else if x > 1.0 {1.0} else {x};
+    ::std::cmp::min((clamped * BANDS as f32) as u32, BANDS - 1)
+}
+
+// one world's raw resources
+#[derive(Debug,Clone)]
+pub struct ResourceGenerator {
+    super_seed: u64,
+    thresholds: MaterialThresholds,
+}
+
+impl ResourceGenerator {
+    pub fn for_world(w: &World) -> ResourceGenerator {
+        ResourceGenerator {
+            super_seed: w.get_primitive().get_super_seed(),
+            thresholds: w.material_thresholds(),
+        }
+    }
+
+    // how much this planet scales the given attribute of the given material
+    fn planet_factor(&self, mat: Material, t: AttributeType) -> f32 {
+        let mut rng = Isaac64Rng::from_seed(&[self.super_seed, mat as u64, t as u64]);
+        let (lo, hi) = PLANET_VARIATION;
+        lo + rng.gen::<f32>() * (hi - lo)
+    }
+
+    // the names and attributes of what can be gathered from `mat` where `data` was sampled
+    pub fn raw_blueprints(&self, mat: Material, data: &PointSampleData) -> Vec<(String, Attributes)> {
+        let altitude = band((data.height - self.thresholds.get_water_level()) / ALTITUDE_RANGE);
+        let warmth = band((data.temp - self.thresholds.get_snow_below_temp()) / WARMTH_RANGE);
+        // both as 0.0 .. 1.0 again, but only ever BANDS different values
+        let high = altitude as f32 / (BANDS - 1) as f32;
+        let warm = warmth as f32 / (BANDS - 1) as f32;
+        let cold = 1.0 - warm;
+
+        let prefix = match (warmth, altitude) {
+            (0, a) if a == BANDS-1 => "frozen highland ",
+            (0, _) => "frozen ",
+            (w, 0) if w == BANDS-1 => "tropical lowland ",
+            (_, a) if a == BANDS-1 => "highland ",
+            (_, 0) => "lowland ",
+            _ => "",
+        };
+        raw_resources(mat).iter().map(|r| {
+            let attributes = r.attributes.iter().map(|&(t, v)| {
+                let climate = match t {
+                    Hardness => 1.0 + 0.5 * cold,
+                    Flammability => 1.0 - 0.5 * cold,
+                    Starch => 0.5 + warm,
+                    Mass => 1.0 + 0.4 * high,
+                    _ => 1.0,
+                };
+                let scaled = v as f32 * climate * self.planet_factor(mat, t);
+                // never scaled away entirely
+                (t, ::std::cmp::max(scaled.round() as u32, 1))
+            }).collect();
+            (format!("{}{}", prefix, r.name), Attributes::new(attributes))
+        }).collect()
+    }
+
+    // the blueprints of everything gatherable here, invented in the pool if they are new
+    pub fn gather(&self, pool: &mut BlueprintPool, mat: Material, data: &PointSampleData) -> Vec<BlueprintID> {
+        self.raw_blueprints(mat, data).into_iter()
+        .map(|(name, atts)| pool.find_or_invent(name, atts))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::world::WorldPrimitive;
+
+    const MATERIALS: [Material; 8] = [
+        Material::Rock, Material::DarkRock, Material::Trees, Material::Grass,
+        Material::Sand, Material::Ice, Material::Snow, Material::Water,
+    ];
+
+    fn generator(seed: u64) -> ResourceGenerator {
+        ResourceGenerator::for_world(&World::new(WorldPrimitive::new(seed, 0.5, 0.5)))
+    }
+
+    // `warmth` and `altitude` from 0.0 (the lowest band) to 1.0 (the highest)
+    fn sample(g: &ResourceGenerator, warmth: f32, altitude: f32) -> PointSampleData {
+        PointSampleData {
+            temp: g.thresholds.get_snow_below_temp() + warmth * WARMTH_RANGE,
+            height: g.thresholds.get_water_level() + altitude * ALTITUDE_RANGE,
+            x_slope: 0.0,
+            y_slope: 0.0,
+            slope: 0.0,
+        }
+    }
+
+    fn everything(g: &ResourceGenerator, data: &PointSampleData) -> Vec<(String, Attributes)> {
+        MATERIALS.iter().flat_map(|m| g.raw_blueprints(*m, data)).collect()
+    }
+
+    fn named<'a>(resources: &'a [(String, Attributes)], name: &str) -> &'a Attributes {
+        &resources.iter().find(|r| r.0 == name).expect(name).1
+    }
+
+    #[test]
+    fn same_planet_same_resources() {
+        let (a, b) = (generator(3), generator(3));
+        let mut pools = (BlueprintPool::new(), BlueprintPool::new());
+        for &(warmth, altitude) in [(0.0, 0.0), (0.5, 0.2), (1.0, 1.0)].iter() {
+            let data = sample(&a, warmth, altitude);
+            assert_eq!(everything(&a, &data), everything(&b, &data));
+            for m in MATERIALS.iter() {
+                assert_eq!(a.gather(&mut pools.0, *m, &data), b.gather(&mut pools.1, *m, &data));
+            }
+        }
+        // gathering the same again finds what was invented the first time
+        let before = pools.0.len();
+        a.gather(&mut pools.0, Material::Rock, &sample(&a, 0.0, 0.0));
+        assert_eq!(pools.0.len(), before);
+    }
+
+    #[test]
+    fn planets_differ() {
+        let found: Vec<Vec<(String, Attributes)>> = (0..5).map(|seed| {
+            let g = generator(seed);
+            let data = sample(&g, 0.5, 0.5);
+            everything(&g, &data)
+        }).collect();
+        for i in 0..found.len() {
+            for j in 0..i {
+                assert!(found[i] != found[j], "planets {} and {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn climate_and_altitude_pull_the_right_way() {
+        let g = generator(1);
+        let cold = g.raw_blueprints(Material::Rock, &sample(&g, 0.0, 0.5));
+        let warm = g.raw_blueprints(Material::Rock, &sample(&g, 1.0, 0.5));
+        assert!(named(&cold, "frozen stone").get(Hardness) > named(&warm, "stone").get(Hardness));
+        assert_eq!(named(&cold, "frozen stone").get(Mass), named(&warm, "stone").get(Mass));
+
+        let cold = g.raw_blueprints(Material::Grass, &sample(&g, 0.0, 0.5));
+        let warm = g.raw_blueprints(Material::Grass, &sample(&g, 1.0, 0.5));
+        assert!(named(&cold, "frozen seeds").get(Starch) < named(&warm, "seeds").get(Starch));
+        assert!(named(&cold, "frozen fibre").get(Flammability) < named(&warm, "fibre").get(Flammability));
+
+        let low = g.raw_blueprints(Material::Rock, &sample(&g, 0.5, 0.0));
+        let high = g.raw_blueprints(Material::Rock, &sample(&g, 0.5, 1.0));
+        assert!(named(&low, "lowland stone").get(Mass) < named(&high, "highland stone").get(Mass));
+        assert_eq!(named(&low, "lowland stone").get(Hardness), named(&high, "highland stone").get(Hardness));
+
+        // cells in the same band yield the same things
+        assert_eq!(g.raw_blueprints(Material::Rock, &sample(&g, 0.3, 0.3)), g.raw_blueprints(Material::Rock, &sample(&g, 0.4, 0.4)));
+        let tropical = g.raw_blueprints(Material::Grass, &sample(&g, 1.0, 0.0));
+        assert_eq!(named(&tropical, "tropical lowland seeds").get(Length), 1);
+    }
+}
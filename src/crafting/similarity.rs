@@ -0,0 +1,40 @@
+use std::fmt::Debug;
+use super::Attributes;
+
+// how alike two sets of attributes are. 1.0 means identical
+pub trait Similarity: Debug {
+    fn similarity(&self, a: &Attributes, b: &Attributes) -> f32;
+
+    // if similarity(query, b) > threshold implies that the L1 distance between
+    // their attribute vectors is at most some bound, that bound. lets the pool
+    // skip everything further away. None means every blueprint must be checked
+    fn l1_bound(&self, _query: &Attributes, _threshold: f32) -> Option<u32> {
+        None
+    }
+}
+
+// shared / (shared + different), summed over all attributes.
+// eg: mass 10 vs 8 shares 8 and differs by 2
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct Overlap;
+
+impl Similarity for Overlap {
+    fn similarity(&self, a: &Attributes, b: &Attributes) -> f32 {
+        a.similarity_coefficient(b)
+    }
+
+    // `different` IS the L1 distance, and `shared` is at most the query's total.
+    // so shared / (shared + different) > t  needs  different < total * (1-t) / t
+    fn l1_bound(&self, query: &Attributes, threshold: f32) -> Option<u32> {
+        if threshold <= 0.0 {return None}
+        let total: u32 = query.iter().map(|(_, v)| v).sum();
+        Some((total as f32 * (1.0 - threshold) / threshold).ceil() as u32)
+    }
+}
+
+// any plain function will do, without the index's help
+impl Similarity for fn(&Attributes, &Attributes) -> f32 {
+    fn similarity(&self, a: &Attributes, b: &Attributes) -> f32 {
+        self(a, b)
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap,BTreeSet,HashSet};
+use std::io::Write;
+use ::serde_json;
+use super::{BlueprintPool,BlueprintID,Process,CraftError};
+
+/*
+Everything a pool can turn some starting blueprints into, level by level:
+level n+1 holds the blueprints first reached by applying some process or
+recipe to blueprints of level n (combining them with anything already known).
+Exploring invents blueprints in the pool as it goes, just like crafting would.
+*/
+
+#[derive(Debug,Clone,PartialEq,Eq,Hash,Serialize,Deserialize)]
+pub enum Method {
+    Process(Process),
+    // by name
+    Recipe(String),
+}
+
+impl Method {
+    fn label(&self) -> String {
+        match self {
+            &Method::Process(p) => format!("{:?}", p),
+            &Method::Recipe(ref name) => name.clone(),
+        }
+    }
+}
+
+// inputs -> method -> outputs
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct RecipeEdge {
+    inputs: Vec<BlueprintID>,
+    method: Method,
+    outputs: Vec<BlueprintID>,
+}
+
+impl RecipeEdge {
+    pub fn get_inputs(&self) -> &[BlueprintID] {&self.inputs}
+    pub fn get_method(&self) -> &Method {&self.method}
+    pub fn get_outputs(&self) -> &[BlueprintID] {&self.outputs}
+
+    // the result is (one of) the inputs again, eg: coating something that doesn't conduct
+    pub fn is_fixed_point(&self) -> bool {
+        self.outputs.iter().any(|o| self.inputs.contains(o))
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct TechTree {
+    // the level each blueprint was first reached at
+    levels: BTreeMap<BlueprintID, usize>,
+    edges: Vec<RecipeEdge>,
+}
+
+#[derive(Serialize)]
+struct ExportedBlueprint<'a> {
+    id: BlueprintID,
+    name: &'a str,
+    level: usize,
+}
+
+#[derive(Serialize)]
+struct ExportedTree<'a> {
+    blueprints: Vec<ExportedBlueprint<'a>>,
+    edges: &'a [RecipeEdge],
+    cycles: Vec<Vec<BlueprintID>>,
+    fixed_points: Vec<&'a RecipeEdge>,
+}
+
+// a process or recipe not applying to something isn't a problem, just a dead end
+fn outputs_of(result: Result<Vec<BlueprintID>, CraftError>) -> Result<Option<Vec<BlueprintID>>, CraftError> {
+    match result {
+        Ok(outputs) => Ok(Some(outputs)),
+        Err(CraftError::NotApplicable(_)) | Err(CraftError::RecipeDoesNotApply(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl TechTree {
+    // `max_depth` levels beyond the roots, which are level 0
+    pub fn explore(pool: &mut BlueprintPool, roots: &[BlueprintID], max_depth: usize) -> Result<TechTree, CraftError> {
+        let mut levels: BTreeMap<BlueprintID, usize> = BTreeMap::new();
+        for r in roots.iter() {
+            if pool.get(*r).is_none() {
+                return Err(CraftError::UnknownBlueprint(*r));
+            }
+            levels.insert(*r, 0);
+        }
+        let recipes: Vec<String> = pool.get_recipes().iter().map(|r| r.get_name().to_owned()).collect();
+        let mut edges = vec![];
+        let mut tried: HashSet<(Vec<BlueprintID>, Method)> = HashSet::new();
+        let mut frontier: Vec<BlueprintID> = levels.keys().cloned().collect();
+
+        for level in 0..max_depth {
+            let known: Vec<BlueprintID> = levels.keys().cloned().collect();
+            let mut attempts: Vec<(Vec<BlueprintID>, Method)> = vec![];
+            for &bp in frontier.iter() {
+                for p in Process::ALL.iter() {
+                    if p.arity() == 1 {
+                        attempts.push((vec![bp], Method::Process(*p)));
+                    } else {
+                        // frontier blueprints with everything known, both ways around
+                        for &other in known.iter().filter(|o| **o != bp) {
+                            attempts.push((vec![bp, other], Method::Process(*p)));
+                            attempts.push((vec![other, bp], Method::Process(*p)));
+                        }
+                    }
+                }
+                for name in recipes.iter() {
+                    attempts.push((vec![bp], Method::Recipe(name.clone())));
+                }
+            }
+
+            let mut next: BTreeSet<BlueprintID> = BTreeSet::new();
+            for (inputs, method) in attempts {
+                if !tried.insert((inputs.clone(), method.clone())) {continue}
+                let result = match method {
+                    Method::Process(p) => pool.apply_process(&inputs, p),
+                    Method::Recipe(ref name) => pool.apply_recipe(inputs[0], name).map(|o| vec![o]),
+                };
+                let outputs = match outputs_of(result)? {
+                    Some(outputs) => outputs,
+                    None => continue,
+                };
+                for o in outputs.iter() {
+                    if !levels.contains_key(o) {
+                        levels.insert(*o, level + 1);
+                        next.insert(*o);
+                    }
+                }
+                edges.push(RecipeEdge {inputs: inputs, method: method, outputs: outputs});
+            }
+            if next.is_empty() {break}
+            frontier = next.into_iter().collect();
+        }
+        Ok(TechTree {levels: levels, edges: edges})
+    }
+
+    pub fn get_edges(&self) -> &[RecipeEdge] {&self.edges}
+    pub fn level_of(&self, bp_id: BlueprintID) -> Option<usize> {self.levels.get(&bp_id).cloned()}
+
+    // sorted
+    pub fn blueprints(&self) -> Vec<BlueprintID> {
+        self.levels.keys().cloned().collect()
+    }
+
+    pub fn fixed_points(&self) -> Vec<&RecipeEdge> {
+        self.edges.iter().filter(|e| e.is_fixed_point()).collect()
+    }
+
+    // groups of two or more blueprints that can each be made from the others.
+    // (strongly connected components, by Kosaraju.) each sorted, smallest first
+    pub fn cycles(&self) -> Vec<Vec<BlueprintID>> {
+        let mut forward: BTreeMap<BlueprintID, BTreeSet<BlueprintID>> = BTreeMap::new();
+        let mut backward: BTreeMap<BlueprintID, BTreeSet<BlueprintID>> = BTreeMap::new();
+        for e in self.edges.iter() {
+            for i in e.inputs.iter() {
+                for o in e.outputs.iter().filter(|o| *o != i) {
+                    forward.entry(*i).or_insert_with(BTreeSet::new).insert(*o);
+                    backward.entry(*o).or_insert_with(BTreeSet::new).insert(*i);
+                }
+            }
+        }
+        let empty = BTreeSet::new();
+
+        // finishing order of a depth-first search, without recursion
+        let mut order = vec![];
+        let mut visited: HashSet<BlueprintID> = HashSet::new();
+        for &start in self.levels.keys() {
+            if !visited.insert(start) {continue}
+            let mut stack = vec![(start, forward.get(&start).unwrap_or(&empty).iter())];
+            loop {
+                let next = match stack.last_mut() {
+                    Some(&mut (_, ref mut children)) => children.next().cloned(),
+                    None => break,
+                };
+                match next {
+                    Some(child) => if visited.insert(child) {
+                        stack.push((child, forward.get(&child).unwrap_or(&empty).iter()));
+                    },
+                    None => order.push(stack.pop().unwrap().0),
+                }
+            }
+        }
+
+        let mut assigned: HashSet<BlueprintID> = HashSet::new();
+        let mut cycles = vec![];
+        for &root in order.iter().rev() {
+            if !assigned.insert(root) {continue}
+            let mut component = vec![root];
+            let mut stack = vec![root];
+            while let Some(at) = stack.pop() {
+                for &prev in backward.get(&at).unwrap_or(&empty).iter() {
+                    if assigned.insert(prev) {
+                        component.push(prev);
+                        stack.push(prev);
+                    }
+                }
+            }
+            if component.len() > 1 {
+                component.sort();
+                cycles.push(component);
+            }
+        }
+        cycles.sort();
+        cycles
+    }
+
+    // a box per step, pointing from its inputs to its outputs
+    pub fn to_dot(&self, pool: &BlueprintPool) -> String {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut dot = String::from("digraph tech_tree {\n");
+        for (bp, level) in self.levels.iter() {
+            let name = pool.get(*bp).map(|b| b.get_name()).unwrap_or("?");
+            dot.push_str(&format!("    bp{} [label=\"{}\\n#{} level {}\"];\n", bp, escape(name), bp, level));
+        }
+        for (i, e) in self.edges.iter().enumerate() {
+            dot.push_str(&format!("    step{} [shape=box, label=\"{}\"];\n", i, escape(&e.method.label())));
+            for input in e.inputs.iter() {
+                dot.push_str(&format!("    bp{} -> step{};\n", input, i));
+            }
+            for output in e.outputs.iter() {
+                dot.push_str(&format!("    step{} -> bp{};\n", i, output));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn write_json<W: Write>(&self, pool: &BlueprintPool, w: W) -> Result<(), serde_json::Error> {
+        let exported = ExportedTree {
+            blueprints: self.levels.iter().map(|(bp, level)| ExportedBlueprint {
+                id: *bp,
+                name: pool.get(*bp).map(|b| b.get_name()).unwrap_or("?"),
+                level: *level,
+            }).collect(),
+            edges: &self.edges,
+            cycles: self.cycles(),
+            fixed_points: self.fixed_points(),
+        };
+        serde_json::to_writer_pretty(w, &exported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Blueprint,Attributes};
+    use super::super::AttributeType::*;
+
+    // soaking and drying undo each other, so the dough and the soaked dough form a cycle
+    const RECIPES: &'static str = r#"
+{"name": "soak", "prefix": "soaked ", "conditions": [{"attribute": "Starch", "max": 5}], "set": {"Starch": "Starch + 5"}}
+{"name": "dry", "prefix": "dried ", "conditions": [{"attribute": "Starch", "min": 6}], "set": {"Starch": "Starch - 5"}}
+"#;
+
+    fn dough() -> Attributes {
+        Attributes::new(vec![(Mass, 10), (Starch, 1)])
+    }
+
+    fn explored(depth: usize) -> (BlueprintPool, BlueprintID, TechTree) {
+        let mut pool = BlueprintPool::new();
+        pool.load_recipes(RECIPES.as_bytes()).unwrap();
+        let root = pool.invent(Blueprint::new("dough".to_owned(), dough()));
+        let tree = TechTree::explore(&mut pool, &[root], depth).unwrap();
+        (pool, root, tree)
+    }
+
+    fn edge(inputs: Vec<BlueprintID>, method: Method, outputs: Vec<BlueprintID>) -> RecipeEdge {
+        RecipeEdge {inputs: inputs, method: method, outputs: outputs}
+    }
+
+    // blueprints 0 to n-1, named a, b, c...
+    fn pool_of(n: u32) -> BlueprintPool {
+        let mut pool = BlueprintPool::new();
+        for i in 0..n {
+            let name = ((b'a' + i as u8) as char).to_string();
+            pool.invent(Blueprint::new(name, Attributes::new(vec![(Mass, i + 1)])));
+        }
+        pool
+    }
+
+    // 0 -> 1 -> 2 -> 0 and 3 <-> 4 are cycles. 5 coats into itself, and combining 5 and 6 gives 6
+    fn hand_built() -> TechTree {
+        let levels = vec![(0, 0), (1, 1), (2, 2), (3, 3), (4, 4), (5, 4), (6, 0)].into_iter().collect();
+        TechTree {levels: levels, edges: vec![
+            edge(vec![0], Method::Process(Process::Heat), vec![1]),
+            edge(vec![1], Method::Recipe("soak".to_owned()), vec![2]),
+            edge(vec![2], Method::Process(Process::Coat), vec![0]),
+            edge(vec![2], Method::Process(Process::Heat), vec![3]),
+            edge(vec![3], Method::Process(Process::Cut), vec![4, 5]),
+            edge(vec![4], Method::Recipe("dry".to_owned()), vec![3]),
+            edge(vec![5], Method::Process(Process::Coat), vec![5]),
+            edge(vec![5, 6], Method::Process(Process::Combine), vec![6]),
+        ]}
+    }
+
+    #[test]
+    fn levels_are_the_fewest_steps_from_the_roots() {
+        let (pool, root, tree) = explored(2);
+        assert_eq!(tree.level_of(root), Some(0));
+        let soaked = pool.find_similar(&dough().with_values(vec![(Starch, 6)])).unwrap();
+        let flattened = pool.find_similar(&dough().same_as_but(vec![(Hardness, 5)])).unwrap();
+        assert_eq!(tree.level_of(soaked), Some(1));
+        assert_eq!(tree.level_of(flattened), Some(1));
+
+        for bp in tree.blueprints() {
+            let level = tree.level_of(bp).unwrap();
+            assert!(level <= 2);
+            if bp == root {continue}
+            // one more than the latest input of the quickest way to make it
+            let quickest = tree.get_edges().iter()
+                .filter(|e| e.get_outputs().contains(&bp))
+                .map(|e| e.get_inputs().iter().map(|i| tree.level_of(*i).unwrap()).max().unwrap())
+                .min();
+            assert_eq!(quickest.map(|l| l + 1), Some(level), "blueprint {}", bp);
+        }
+        for e in tree.get_edges() {
+            assert!(e.get_inputs().iter().all(|i| tree.level_of(*i).map(|l| l < 2).unwrap_or(false)));
+        }
+        // nothing can cut a thing without length
+        assert!(tree.get_edges().iter().all(|e| e.get_method() != &Method::Process(Process::Cut)));
+
+        let mut pool = BlueprintPool::new();
+        match TechTree::explore(&mut pool, &[7], 2) {
+            Err(CraftError::UnknownBlueprint(7)) => (),
+            other => panic!("expected an unknown blueprint, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn finds_the_cycle_and_fixed_points_it_was_built_with() {
+        let (pool, root, tree) = explored(2);
+        let soaked = pool.find_similar(&dough().with_values(vec![(Starch, 6)])).unwrap();
+        let cycles = tree.cycles();
+        assert!(cycles.contains(&vec![root, soaked]), "{:?}", cycles);
+        // in every group, each can be made from each other one
+        let reaches = |from: BlueprintID, to: BlueprintID| {
+            let mut seen = vec![from];
+            let mut i = 0;
+            while i < seen.len() {
+                let at = seen[i];
+                for e in tree.get_edges().iter().filter(|e| e.get_inputs().contains(&at)) {
+                    for o in e.get_outputs() {
+                        if !seen.contains(o) {seen.push(*o)}
+                    }
+                }
+                i += 1;
+            }
+            seen.contains(&to)
+        };
+        for group in cycles.iter() {
+            for a in group.iter() {
+                assert!(group.iter().all(|b| reaches(*a, *b)), "{:?}", group);
+            }
+        }
+        // the dough doesn't conduct, so coating it changes nothing
+        let coated = edge(vec![root], Method::Process(Process::Coat), vec![root]);
+        assert!(tree.fixed_points().contains(&&coated));
+        assert!(tree.fixed_points().iter().all(|e| e.is_fixed_point()));
+    }
+
+    #[test]
+    fn cycles_of_a_hand_built_tree() {
+        let tree = hand_built();
+        assert_eq!(tree.cycles(), vec![vec![0, 1, 2], vec![3, 4]]);
+        let fixed: Vec<RecipeEdge> = tree.fixed_points().into_iter().cloned().collect();
+        assert_eq!(fixed, vec![
+            edge(vec![5], Method::Process(Process::Coat), vec![5]),
+            edge(vec![5, 6], Method::Process(Process::Combine), vec![6]),
+        ]);
+    }
+
+    #[test]
+    fn dot_export() {
+        let mut pool = pool_of(1);
+        pool.invent(Blueprint::new("\"hot\" a".to_owned(), Attributes::new(vec![(Mass, 9)])));
+        let levels = vec![(0, 0), (1, 1)].into_iter().collect();
+        let tree = TechTree {levels: levels, edges: vec![
+            edge(vec![0], Method::Process(Process::Heat), vec![1]),
+            edge(vec![1], Method::Recipe("soak".to_owned()), vec![1]),
+        ]};
+        assert_eq!(tree.to_dot(&pool), concat!(
+            "digraph tech_tree {\n",
+            "    bp0 [label=\"a\\n#0 level 0\"];\n",
+            "    bp1 [label=\"\\\"hot\\\" a\\n#1 level 1\"];\n",
+            "    step0 [shape=box, label=\"Heat\"];\n",
+            "    bp0 -> step0;\n",
+            "    step0 -> bp1;\n",
+            "    step1 [shape=box, label=\"soak\"];\n",
+            "    bp1 -> step1;\n",
+            "    step1 -> bp1;\n",
+            "}\n",
+        ));
+    }
+
+    fn value(s: &str) -> serde_json::Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn json_export() {
+        let pool = pool_of(7);
+        let mut out = vec![];
+        hand_built().write_json(&pool, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["blueprints"][5], value(r#"{"id": 5, "name": "f", "level": 4}"#));
+        assert_eq!(json["edges"].as_array().unwrap().len(), 8);
+        assert_eq!(json["edges"][1]["method"], value(r#"{"Recipe": "soak"}"#));
+        assert_eq!(json["cycles"], value(r#"[[0, 1, 2], [3, 4]]"#));
+        assert_eq!(json["fixed_points"][0]["inputs"], value(r#"[5]"#));
+        assert_eq!(json["fixed_points"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn exports_are_the_same_every_time() {
+        let export = || {
+            let (pool, _, tree) = explored(2);
+            let mut json = vec![];
+            tree.write_json(&pool, &mut json).unwrap();
+            (tree.to_dot(&pool), json)
+        };
+        let (dot, json) = export();
+        assert!(dot.len() > 0 && json.len() > 0);
+        assert_eq!(export(), (dot, json));
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::{HashMap,HashSet};
+use super::{LocationID,EntityID};
+use super::points::DPoint2;
+use super::primitive::AppliesDiff;
+
+/*
+Entities are just IDs. what an entity IS comes from which components it has:
+    kind        every entity
+    position    anything lying around in a location (not items in an inventory)
+    inventory   players and npcs
+    health      players and npcs
+All changes go through EntityDiffs, so the server can broadcast exactly what it applied.
+*/
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,Serialize,Deserialize)]
+pub enum EntityKind {
+    Player, Npc, Item,
+}
+
+impl EntityKind {
+    fn is_living(self) -> bool {
+        self != EntityKind::Item
+    }
+}
+
+const DEFAULT_HEALTH: u32 = 100;
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,Serialize,Deserialize)]
+pub struct EntityPosition {
+    pub lid: LocationID,
+    pub cell: DPoint2,
+}
+
+impl EntityPosition {
+    pub fn new(lid: LocationID, cell: DPoint2) -> EntityPosition {
+        EntityPosition {lid: lid, cell: cell}
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Serialize,Deserialize)]
+pub enum EntityDiff {
+    Spawn(EntityID, EntityKind, EntityPosition),
+    Despawn(EntityID),
+    // may also move the entity into another location
+    Move(EntityID, EntityPosition),
+    SetHealth(EntityID, u32),
+    // (holder, item). the item leaves its location for the holder's inventory.
+    // only from the cell the holder is standing on
+    PickUp(EntityID, EntityID),
+    // (holder, item). the item lands where the holder stands
+    Drop(EntityID, EntityID),
+}
+
+#[derive(Debug)]
+pub struct EntityStore {
+    next_id: EntityID,
+    kinds: HashMap<EntityID, EntityKind>,
+    positions: HashMap<EntityID, EntityPosition>,
+    inventories: HashMap<EntityID, Vec<EntityID>>,
+    health: HashMap<EntityID, Health>,
+    by_location: HashMap<LocationID, HashSet<EntityID>>,
+}
+
+impl EntityStore {
+    pub fn new() -> EntityStore {
+        EntityStore {
+            next_id: 0,
+            kinds: HashMap::new(),
+            positions: HashMap::new(),
+            inventories: HashMap::new(),
+            health: HashMap::new(),
+            by_location: HashMap::new(),
+        }
+    }
+
+    // allocates an ID and applies the spawn. the returned diff is what others need to hear about
+    pub fn spawn(&mut self, kind: EntityKind, at: EntityPosition) -> (EntityID, EntityDiff) {
+        let eid = self.next_id;
+        let diff = EntityDiff::Spawn(eid, kind, at);
+        self.apply(&diff);
+        (eid, diff)
+    }
+
+    pub fn exists(&self, eid: EntityID) -> bool {self.kinds.contains_key(&eid)}
+    pub fn kind_of(&self, eid: EntityID) -> Option<EntityKind> {self.kinds.get(&eid).cloned()}
+    pub fn position_of(&self, eid: EntityID) -> Option<EntityPosition> {self.positions.get(&eid).cloned()}
+    pub fn health_of(&self, eid: EntityID) -> Option<Health> {self.health.get(&eid).cloned()}
+
+    pub fn inventory_of(&self, eid: EntityID) -> &[EntityID] {
+        self.inventories.get(&eid).map(|i| &i[..]).unwrap_or(&[])
+    }
+
+    // sorted by ID
+    pub fn in_location(&self, lid: LocationID) -> Vec<EntityID> {
+        let mut v: Vec<EntityID> = self.by_location.get(&lid)
+            .map(|s| s.iter().cloned().collect())
+            .unwrap_or(vec![]);
+        v.sort();
+        v
+    }
+
+    pub fn at_cell(&self, lid: LocationID, cell: DPoint2) -> Vec<EntityID> {
+        self.in_location(lid).into_iter()
+        .filter(|e| self.positions[e].cell == cell)
+        .collect()
+    }
+
+    // the diff that would (re)create the entity as it is, eg: for a client that just arrived
+    pub fn spawn_diff_for(&self, eid: EntityID) -> Option<EntityDiff> {
+        match (self.kind_of(eid), self.position_of(eid)) {
+            (Some(kind), Some(at)) => Some(EntityDiff::Spawn(eid, kind, at)),
+            _ => None,
+        }
+    }
+
+    fn set_position(&mut self, eid: EntityID, at: Option<EntityPosition>) {
+        if let Some(old) = self.positions.remove(&eid) {
+            let now_empty = match self.by_location.get_mut(&old.lid) {
+                Some(set) => {set.remove(&eid); set.is_empty()},
+                None => false,
+            };
+            if now_empty {
+                self.by_location.remove(&old.lid);
+            }
+        }
+        if let Some(at) = at {
+            self.positions.insert(eid, at);
+            self.by_location.entry(at.lid).or_insert_with(HashSet::new).insert(eid);
+        }
+    }
+
+    fn remove_from_inventories(&mut self, item: EntityID) {
+        for inventory in self.inventories.values_mut() {
+            inventory.retain(|i| *i != item);
+        }
+    }
+}
+
+impl AppliesDiff<EntityDiff> for EntityStore {
+    // diffs that don't make sense for the current state (eg: moving a despawned entity) are ignored
+    fn apply(&mut self, diff: &EntityDiff) {
+        match diff {
+            &EntityDiff::Spawn(eid, kind, at) => {
+                if self.exists(eid) {return}
+                self.kinds.insert(eid, kind);
+                self.set_position(eid, Some(at));
+                if kind.is_living() {
+                    self.inventories.insert(eid, vec![]);
+                    self.health.insert(eid, Health {current: DEFAULT_HEALTH, max: DEFAULT_HEALTH});
+                }
+                if eid >= self.next_id {
+                    self.next_id = eid + 1;
+                }
+            },
+            &EntityDiff::Despawn(eid) => {
+                if self.kinds.remove(&eid).is_none() {return}
+                // whatever it carried is lost with it
+                if let Some(carried) = self.inventories.remove(&eid) {
+                    for item in carried {
+                        self.apply(&EntityDiff::Despawn(item));
+                    }
+                }
+                self.set_position(eid, None);
+                self.health.remove(&eid);
+                self.remove_from_inventories(eid);
+            },
+            &EntityDiff::Move(eid, at) => {
+                if self.positions.contains_key(&eid) {
+                    self.set_position(eid, Some(at));
+                }
+            },
+            &EntityDiff::SetHealth(eid, value) => {
+                if let Some(h) = self.health.get_mut(&eid) {
+                    h.current = value.min(h.max);
+                }
+            },
+            &EntityDiff::PickUp(holder, item) => {
+                if self.kind_of(item) != Some(EntityKind::Item)
+                || !self.inventories.contains_key(&holder) {return}
+                // no reaching into other locations, or across the room
+                match (self.positions.get(&holder), self.positions.get(&item)) {
+                    (Some(h), Some(i)) if h == i => (),
+                    _ => return,
+                }
+                self.set_position(item, None);
+                self.inventories.get_mut(&holder).unwrap().push(item);
+            },
+            &EntityDiff::Drop(holder, item) => {
+                let at = match self.positions.get(&holder) {
+                    Some(at) => *at,
+                    None => return,
+                };
+                let carried = self.inventories.get(&holder).map(|i| i.contains(&item)).unwrap_or(false);
+                if !carried {return}
+                self.remove_from_inventories(item);
+                self.set_position(item, Some(at));
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_up_only_from_the_holders_cell() {
+        let mut store = EntityStore::new();
+        let here = EntityPosition::new(1, DPoint2::new(2, 2));
+        let player = store.spawn(EntityKind::Player, here).0;
+        let elsewhere = store.spawn(EntityKind::Item, EntityPosition::new(2, DPoint2::new(2, 2))).0;
+        let next_door = store.spawn(EntityKind::Item, EntityPosition::new(1, DPoint2::new(3, 2))).0;
+        let underfoot = store.spawn(EntityKind::Item, here).0;
+
+        store.apply(&EntityDiff::PickUp(player, elsewhere));
+        store.apply(&EntityDiff::PickUp(player, next_door));
+        assert!(store.inventory_of(player).is_empty());
+        assert!(store.position_of(elsewhere).is_some());
+        assert!(store.position_of(next_door).is_some());
+
+        store.apply(&EntityDiff::PickUp(player, underfoot));
+        assert_eq!(store.inventory_of(player), &[underfoot]);
+        assert_eq!(store.position_of(underfoot), None);
+    }
+}
@@ -3,6 +3,12 @@ extern crate lazy_static;
 extern crate array_init;
 extern crate rand;
 extern crate noise;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate piston_window;
+extern crate ai_behavior;
 
 // mod asciireen;
 
@@ -11,12 +17,21 @@ mod procedural;
 mod world;
 mod portals;
 mod points;
+mod primitive;
+mod saver_loader;
+mod registry;
+mod server;
+mod protocol;
+mod entities;
+mod npc;
+mod crafting;
 use world::{WorldPrimitive,World};
 use points::*;
 use self::rand::{SeedableRng,Rng,Isaac64Rng};
 
 
 pub type LocationID = u64;
+pub type EntityID = u64;
 
 use std::path::Path;
 
@@ -0,0 +1,314 @@
+use ::ai_behavior::{Behavior,State,ActionArgs,Status,Action,Select,Sequence,Wait,Success,Failure,Running};
+use ::piston_window::{Event,Loop,UpdateArgs};
+use ::rand::{SeedableRng,Rng,Isaac64Rng};
+use super::{LocationID,EntityID};
+use super::points::DPoint2;
+use super::entities::{EntityStore,EntityDiff,EntityPosition};
+use super::primitive::AppliesDiff;
+use super::world::Material;
+use super::world::location::Location;
+use super::world::pathfinding::{FlowFieldCache,MovementCosts,Diagonals};
+
+/*
+NPCs in one Location, each driven by its own behavior tree.
+
+The simulation only ever advances in fixed steps of TICK_SECONDS, fed to the
+trees as update events. Nothing reads a clock or a window, and all randomness
+comes from one rng seeded by the caller, so the same seed, location and NPCs
+always produce the same diffs.
+
+Every movement action costs a whole tick. conditions are free.
+*/
+
+pub const TICK_SECONDS: f64 = 0.1;
+
+// how long a wanderer idles between steps
+const WANDER_PAUSE: f64 = 0.5;
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum NpcAction {
+    // conditions
+    NearWater,
+    AtExit(usize),
+    // movement
+    StepAwayFromWater,
+    WanderStep,
+    StepTowardExit(usize),
+    // despawns the npc. it carries on along the link, out of this simulation
+    Leave,
+}
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum NpcRole {
+    Wanderer,
+    // heads for the exit of the given link index, then leaves
+    Traveller(usize),
+}
+
+impl NpcRole {
+    pub fn behavior(self) -> Behavior<NpcAction> {
+        let flee = Sequence(vec![Action(NpcAction::NearWater), Action(NpcAction::StepAwayFromWater)]);
+        let wander = Sequence(vec![Action(NpcAction::WanderStep), Wait(WANDER_PAUSE)]);
+        match self {
+            NpcRole::Wanderer => Select(vec![flee, wander]),
+            NpcRole::Traveller(link) => Select(vec![
+                Sequence(vec![Action(NpcAction::AtExit(link)), Action(NpcAction::Leave)]),
+                Action(NpcAction::StepTowardExit(link)),
+                // stuck behind something. shuffle around and try again later
+                wander,
+            ]),
+        }
+    }
+}
+
+// up, right, down, left, across wrapping edges too. a fixed order, so that rng draws line up between runs
+fn walkable_neighbours(location: &Location, cell: DPoint2) -> Vec<DPoint2> {
+    let materials = location.get_materials();
+    materials.neighbours4(cell).into_iter()
+        .filter(|c| materials.at(*c) != Some(&Material::Water))
+        .collect()
+}
+
+// water cells among the cell itself and its 8 surrounding cells
+fn water_around(location: &Location, cell: DPoint2) -> usize {
+    let materials = location.get_materials();
+    let mut cells = materials.neighbours8(cell);
+    cells.push(cell);
+    cells.iter().filter(|c| materials.at(**c) == Some(&Material::Water)).count()
+}
+
+struct Npc {
+    eid: EntityID,
+    role: NpcRole,
+    state: State<NpcAction, ()>,
+}
+
+// what an action did to its npc during a tick
+enum Outcome {
+    Stay,
+    MoveTo(DPoint2),
+    Left,
+}
+
+pub struct NpcSim {
+    lid: LocationID,
+    rng: Isaac64Rng,
+    // in the order they were added
+    npcs: Vec<Npc>,
+    // travellers all heading for the same exit share one field
+    flows: FlowFieldCache,
+    ticks: u64,
+}
+
+impl NpcSim {
+    pub fn new(lid: LocationID, seed: u64) -> NpcSim {
+        NpcSim {
+            lid: lid,
+            rng: Isaac64Rng::from_seed(&[seed]),
+            npcs: vec![],
+            flows: FlowFieldCache::new(MovementCosts::walking(), Diagonals::Never),
+            ticks: 0,
+        }
+    }
+
+    pub fn get_lid(&self) -> LocationID {self.lid}
+    pub fn get_ticks(&self) -> u64 {self.ticks}
+    pub fn npc_count(&self) -> usize {self.npcs.len()}
+
+    // `eid` must already exist in the store the sim is ticked with
+    pub fn add_npc(&mut self, eid: EntityID, role: NpcRole) {
+        self.npcs.push(Npc {eid: eid, role: role, state: State::new(role.behavior())});
+    }
+
+    // must be called whenever the materials of the location change
+    pub fn location_changed(&mut self) {
+        self.flows.clear();
+    }
+
+    pub fn role_of(&self, eid: EntityID) -> Option<NpcRole> {
+        self.npcs.iter().find(|n| n.eid == eid).map(|n| n.role)
+    }
+
+    // advances every npc by one step. the diffs are NOT applied; the caller applies them
+    // (eg: through Server::apply_entity_diff) before the next tick
+    pub fn tick(&mut self, location: &Location, entities: &EntityStore) -> Vec<EntityDiff> {
+        let lid = self.lid;
+        // npcs that died or wandered off some other way are forgotten
+        self.npcs.retain(|n| entities.position_of(n.eid).map(|p| p.lid == lid).unwrap_or(false));
+
+        let update = Event::Loop(Loop::Update(UpdateArgs {dt: TICK_SECONDS}));
+        let rng = &mut self.rng;
+        let flows = &mut self.flows;
+        let mut diffs = vec![];
+        let mut departed = vec![];
+        for npc in self.npcs.iter_mut() {
+            let start = entities.position_of(npc.eid).unwrap().cell;
+            let mut outcome = Outcome::Stay;
+            let (status, _) = npc.state.event(&update, &mut |args: ActionArgs<Event, NpcAction, ()>| {
+                act(*args.action, args.dt, start, location, &mut *rng, &mut *flows, &mut outcome)
+            });
+            // trees are one-shot decisions. start over once one has run its course
+            if status != Running {
+                npc.state = State::new(npc.role.behavior());
+            }
+            match outcome {
+                Outcome::Stay => (),
+                Outcome::MoveTo(cell) => diffs.push(EntityDiff::Move(npc.eid, EntityPosition::new(lid, cell))),
+                Outcome::Left => {
+                    diffs.push(EntityDiff::Despawn(npc.eid));
+                    departed.push(npc.eid);
+                },
+            }
+        }
+        self.npcs.retain(|n| !departed.contains(&n.eid));
+        self.ticks += 1;
+        diffs
+    }
+
+    // ticks `ticks` times, applying the diffs to `entities` as it goes. returns every diff in order
+    pub fn run(&mut self, ticks: u64, location: &Location, entities: &mut EntityStore) -> Vec<EntityDiff> {
+        let mut all = vec![];
+        for _ in 0..ticks {
+            for d in self.tick(location, entities) {
+                entities.apply(&d);
+                all.push(d);
+            }
+        }
+        all
+    }
+}
+
+// one call of a leaf action. `at` is where the npc stood when the tick began
+fn act<R: Rng>(action: NpcAction, dt: f64, at: DPoint2, location: &Location, rng: &mut R, flows: &mut FlowFieldCache, outcome: &mut Outcome)
+-> (Status, f64) {
+    let condition = |holds: bool| (if holds {Success} else {Failure}, dt);
+    let moving = match action {
+        NpcAction::NearWater | NpcAction::AtExit(_) => false,
+        _ => true,
+    };
+    // this tick was already spent on a move
+    if moving && dt <= 0.0 {
+        return (Running, 0.0);
+    }
+    let step_to = |outcome: &mut Outcome, cell: Option<DPoint2>| match cell {
+        Some(cell) => {
+            *outcome = Outcome::MoveTo(cell);
+            (Success, 0.0)
+        },
+        None => (Failure, dt),
+    };
+    match action {
+        NpcAction::NearWater => condition(water_around(location, at) > 0),
+        NpcAction::AtExit(link) => condition(location.exit_for_link(link).map(|e| e.get_cell() == at).unwrap_or(false)),
+        NpcAction::StepAwayFromWater => {
+            let here = water_around(location, at);
+            let best = walkable_neighbours(location, at).into_iter()
+                .map(|c| (water_around(location, c), c))
+                .filter(|&(w, _)| w < here)
+                .min_by_key(|&(w, _)| w)
+                .map(|(_, c)| c);
+            step_to(outcome, best)
+        },
+        NpcAction::WanderStep => {
+            let options = walkable_neighbours(location, at);
+            let choice = rng.choose(&options).cloned();
+            step_to(outcome, choice)
+        },
+        NpcAction::StepTowardExit(link) => {
+            // exits that can't be walked to fall through to the next branch of the tree
+            let target = match location.exit_for_link(link) {
+                Some(e) if e.is_land_link() => e.get_cell(),
+                _ => return (Failure, dt),
+            };
+            let next = flows.get(location.get_materials(), target).next_step(location.get_materials(), at);
+            step_to(outcome, next)
+        },
+        NpcAction::Leave => {
+            *outcome = Outcome::Left;
+            (Success, 0.0)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::entities::EntityKind;
+    use super::super::world::{World,WorldPrimitive};
+    use super::super::world::pathfinding::FlowField;
+
+    const LID: LocationID = 5;
+
+    fn location() -> Location {
+        let w = World::new(WorldPrimitive::new(0, 0.5, 0.5));
+        Location::generate(&w.location_primitive(0, 7).unwrap())
+    }
+
+    // a handful of wanderers spread over the dry cells
+    fn wanderers(location: &Location, sim: &mut NpcSim) -> EntityStore {
+        let mut store = EntityStore::new();
+        let dims = location.get_dimensions();
+        let dry: Vec<DPoint2> = (0..dims.y).flat_map(|y| (0..dims.x).map(move |x| DPoint2::new(x, y)))
+            .filter(|c| location.material_at(*c) != Material::Water)
+            .collect();
+        for i in 0..6 {
+            let (eid, _) = store.spawn(EntityKind::Npc, EntityPosition::new(LID, dry[i * dry.len() / 6]));
+            sim.add_npc(eid, NpcRole::Wanderer);
+        }
+        store
+    }
+
+    #[test]
+    fn same_seed_same_diffs() {
+        let location = location();
+        let run = |seed: u64| {
+            let mut sim = NpcSim::new(LID, seed);
+            let mut store = wanderers(&location, &mut sim);
+            sim.run(200, &location, &mut store)
+        };
+        let diffs = run(1);
+        assert!(diffs.len() > 0);
+        assert_eq!(diffs, run(1));
+        assert!(diffs != run(2));
+        for d in diffs {
+            if let EntityDiff::Move(_, to) = d {
+                assert!(location.material_at(to.cell) != Material::Water);
+            }
+        }
+    }
+
+    #[test]
+    fn travellers_reach_their_exit_and_leave() {
+        let location = location();
+        let exit = *location.get_exits().iter().find(|e| e.is_land_link()).unwrap();
+        let link = exit.get_link_index();
+        let field = FlowField::towards(location.get_materials(), &MovementCosts::walking(), Diagonals::Never, exit.get_cell());
+        // the furthest cell that can still walk there
+        let start = location.get_materials().cell_iterator()
+            .filter(|c| field.cost_from(*c).is_some())
+            .max_by_key(|c| field.cost_from(*c))
+            .unwrap();
+        let mut expected = vec![];
+        let mut at = start;
+        while let Some(next) = field.next_step(location.get_materials(), at) {
+            expected.push(EntityDiff::Move(0, EntityPosition::new(LID, next)));
+            at = next;
+        }
+        assert_eq!(at, exit.get_cell());
+        assert!(expected.len() > 10);
+
+        let mut sim = NpcSim::new(LID, 3);
+        let mut store = EntityStore::new();
+        let (eid, _) = store.spawn(EntityKind::Npc, EntityPosition::new(LID, start));
+        assert_eq!(eid, 0);
+        sim.add_npc(eid, NpcRole::Traveller(link));
+        assert_eq!(sim.role_of(eid), Some(NpcRole::Traveller(link)));
+
+        // one step a tick along the field, then a tick to leave
+        expected.push(EntityDiff::Despawn(eid));
+        let ticks = expected.len() as u64;
+        assert_eq!(sim.run(ticks + 5, &location, &mut store), expected);
+        assert_eq!(sim.npc_count(), 0);
+        assert_eq!(store.position_of(eid), None);
+    }
+}
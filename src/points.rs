@@ -30,7 +30,7 @@ use ::std::ops::{Add,Sub,Mul,Div};
 //////////////////////////////////////////////////////////////
 
 //continuous point in 2d space
-#[derive(Copy,Clone,Debug)]
+#[derive(Copy,Clone,Debug,Serialize,Deserialize)]
 pub struct CPoint2 {
     pub x: f32,
     pub y: f32,
@@ -56,16 +56,23 @@ impl CPoint2 {
         let dy = self.y - o.y;
         (dx*dx*mult_x + dy*dy*mult_y).sqrt()
     }
+
+    // the cell this point lies in
+    pub fn floor(self) -> DPoint2 {
+        DPoint2::new(self.x.floor() as i32, self.y.floor() as i32)
+    }
 }
 
 impl hash::Hash for CPoint2 {
     fn hash<H>(&self, state: &mut H)
     where H: hash::Hasher {
+        // -0.0 == 0.0, so they have to hash the same as well
+        let unsigned_zero = |v: f32| if v == 0.0 {0.0f32} else {v};
         assert!(!self.x.is_nan());
-        let tx : u32 =  unsafe { mem::transmute(self.x) };
+        let tx : u32 =  unsafe { mem::transmute(unsigned_zero(self.x)) };
         tx.hash(state);
         assert!(!self.y.is_nan());
-        let ty : u32 = unsafe { mem::transmute(self.y) };
+        let ty : u32 = unsafe { mem::transmute(unsigned_zero(self.y)) };
         ty.hash(state);
     }
 }
@@ -113,7 +120,7 @@ impl CPoint3 {
 }
 
 //discrete point in 2d space
-#[derive(Copy,Clone,Debug,PartialEq,Hash,Eq)]
+#[derive(Copy,Clone,Debug,PartialEq,Hash,Eq,Serialize,Deserialize)]
 pub struct DPoint2 {
     pub x: i32,
     pub y: i32,
@@ -1,49 +1,283 @@
-// use std::collections::HashMap;
-use super::{LocationID,CPoint2};
-//
-// //primitives need to only define their ENTRY POINT
-// //the server will resolve this to an exit point
-// use std::hash::{Hash,Hasher};
-// // impl Hash for f32 {
-// //     fn hash<H>(&self, state: &mut H) where H: Hasher {
-// //         state.write_u8(4);
-// //     }
-// // }
-#[derive(Debug,Copy,Clone)]
+use std::collections::{HashMap,HashSet};
+use std::io::{self,Read,Write};
+use super::{LocationID,CPoint2,DPoint2};
+use ::serde_json;
+
+//primitives need to only define their ENTRY POINT
+//the server will resolve this to an exit point
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,Serialize,Deserialize)]
 pub struct UniquePoint {
     lid: LocationID,
     c_pt: CPoint2
 }
-//
-// struct PortalResolver {
-//     mapping: HashMap<UniquePoint,UniquePoint>,
-// }
-//
-// /*
-// client                                          server
-//   |                                                |
-// write(x)      ==TraversePortal(UniquePoint)==>  let x = read().unique_point
-//   |                                             let out = portal_resolver.resolve(x)
-// read()      <==LoadLID(out.lid)====             write(1)
-// read()    <==ApplyDiff(AddEntity(you,out.cout)) write(2)
-//
-//
-//
-// */
-//
-//
-// //make saveable-loadable
-// //used only serverside. output to client is ultimately {Load(LID), addEntity(LID,EID)}
-// impl PortalResolver {
-//     pub fn new() -> PortalResolver {
-//         PortalResolver{mapping: HashMap::new()}
-//     }
-//
-//     pub fn resolve(&self, entry_point: UniquePoint) -> Option<UniquePoint> {
-//         self.mapping.get(entry_point)
-//     }
-//
-//     pub fn define_portal(&mut self, a: UniquePoint, b:UniquePoint) {
-//         self.mapping.insert(a,b);
-//     }
-// }
+
+impl UniquePoint {
+    pub fn new(lid: LocationID, c_pt: CPoint2) -> UniquePoint {
+        UniquePoint {
+            lid: lid,
+            c_pt: c_pt,
+        }
+    }
+    pub fn get_lid(&self) -> LocationID {self.lid}
+    pub fn get_c_pt(&self) -> CPoint2 {self.c_pt}
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Serialize,Deserialize)]
+pub enum Portal {
+    Discrete(DPoint2, LocationID),   //enter LocationID at cell (x,y)
+    Continuous(CPoint2, LocationID), //enter LocationID at pos (x,y)
+    Nonspecific(LocationID),         //enter LocationID wherever it likes
+}
+
+impl Portal {
+    pub fn get_lid(&self) -> LocationID {
+        match self {
+            &Portal::Discrete(_, lid) => lid,
+            &Portal::Continuous(_, lid) => lid,
+            &Portal::Nonspecific(lid) => lid,
+        }
+    }
+
+    // where exactly you come out, if the portal says so
+    pub fn arrival_point(&self) -> Option<UniquePoint> {
+        match self {
+            &Portal::Discrete(cell, lid) => Some(UniquePoint::new(lid, CPoint2::new(cell.x as f32, cell.y as f32))),
+            &Portal::Continuous(c_pt, lid) => Some(UniquePoint::new(lid, c_pt)),
+            &Portal::Nonspecific(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PortalError {
+    UnknownLocation(LocationID),
+    AlreadyDefined(UniquePoint),
+    // a Nonspecific portal has no arrival point to put the way back on
+    NotReversible(Portal),
+    Io(io::Error),
+    Serde(serde_json::Error),
+}
+
+impl From<io::Error> for PortalError {
+    fn from(e: io::Error) -> PortalError {PortalError::Io(e)}
+}
+
+impl From<serde_json::Error> for PortalError {
+    fn from(e: serde_json::Error) -> PortalError {PortalError::Serde(e)}
+}
+
+/*
+client                                          server
+  |                                                |
+write(x)      ==TraversePortal(UniquePoint)==>  let x = read().unique_point
+  |                                             let out = portal_resolver.resolve(x)
+read()      <==LoadLID(out.lid)====             write(1)
+read()    <==ApplyDiff(AddEntity(you,out.cout)) write(2)
+*/
+
+// what actually goes to disk. json maps can't have UniquePoint keys
+#[derive(Serialize,Deserialize)]
+struct SavedPortals {
+    locations: Vec<LocationID>,
+    portals: Vec<(UniquePoint, Portal)>,
+}
+
+//used only serverside. output to client is ultimately {Load(LID), addEntity(LID,EID)}
+#[derive(Debug)]
+pub struct PortalResolver {
+    locations: HashSet<LocationID>,
+    mapping: HashMap<UniquePoint, Portal>,
+}
+
+impl PortalResolver {
+    pub fn new() -> PortalResolver {
+        PortalResolver {
+            locations: HashSet::new(),
+            mapping: HashMap::new(),
+        }
+    }
+
+    // portals may only connect locations the resolver has been told about
+    pub fn register_location(&mut self, lid: LocationID) {
+        self.locations.insert(lid);
+    }
+
+    pub fn knows_location(&self, lid: LocationID) -> bool {
+        self.locations.contains(&lid)
+    }
+
+    pub fn resolve(&self, entry_point: &UniquePoint) -> Option<Portal> {
+        self.mapping.get(entry_point).cloned()
+    }
+
+    fn check_free(&self, entry_point: &UniquePoint) -> Result<(), PortalError> {
+        if !self.knows_location(entry_point.lid) {
+            Err(PortalError::UnknownLocation(entry_point.lid))
+        } else if self.mapping.contains_key(entry_point) {
+            Err(PortalError::AlreadyDefined(*entry_point))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn define_portal(&mut self, entry_point: UniquePoint, exit: Portal) -> Result<(), PortalError> {
+        self.check_free(&entry_point)?;
+        if !self.knows_location(exit.get_lid()) {
+            return Err(PortalError::UnknownLocation(exit.get_lid()));
+        }
+        self.mapping.insert(entry_point, exit);
+        Ok(())
+    }
+
+    // also defines the way back: arriving through `exit` and stepping in again leads to entry_point.
+    // either both portals are defined or neither is
+    pub fn define_bidirectional(&mut self, entry_point: UniquePoint, exit: Portal) -> Result<(), PortalError> {
+        let reverse_entry = exit.arrival_point().ok_or(PortalError::NotReversible(exit))?;
+        self.check_free(&entry_point)?;
+        self.check_free(&reverse_entry)?;
+        if reverse_entry == entry_point {
+            return Err(PortalError::AlreadyDefined(entry_point));
+        }
+        self.mapping.insert(entry_point, exit);
+        self.mapping.insert(reverse_entry, Portal::Continuous(entry_point.c_pt, entry_point.lid));
+        Ok(())
+    }
+
+    pub fn remove_portal(&mut self, entry_point: &UniquePoint) -> Option<Portal> {
+        self.mapping.remove(entry_point)
+    }
+
+    pub fn save<W: Write>(&self, writer: W) -> Result<(), PortalError> {
+        let mut locations: Vec<LocationID> = self.locations.iter().cloned().collect();
+        locations.sort();
+        let mut portals: Vec<(UniquePoint, Portal)> = self.mapping.iter()
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        // stable output, so saves of the same resolver are byte-identical
+        portals.sort_by(|a, b| {
+            (a.0.lid, a.0.c_pt.x.to_bits(), a.0.c_pt.y.to_bits())
+            .cmp(&(b.0.lid, b.0.c_pt.x.to_bits(), b.0.c_pt.y.to_bits()))
+        });
+        serde_json::to_writer(writer, &SavedPortals {locations: locations, portals: portals})?;
+        Ok(())
+    }
+
+    pub fn load<R: Read>(reader: R) -> Result<PortalResolver, PortalError> {
+        let saved: SavedPortals = serde_json::from_reader(reader)?;
+        let mut resolver = PortalResolver::new();
+        for lid in saved.locations {
+            resolver.register_location(lid);
+        }
+        for (entry_point, exit) in saved.portals {
+            resolver.define_portal(entry_point, exit)?;
+        }
+        Ok(resolver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(lid: LocationID, x: f32, y: f32) -> UniquePoint {
+        UniquePoint::new(lid, CPoint2::new(x, y))
+    }
+
+    fn resolver() -> PortalResolver {
+        let mut r = PortalResolver::new();
+        for lid in 1..4 {
+            r.register_location(lid);
+        }
+        r
+    }
+
+    #[test]
+    fn resolves_each_kind_of_portal() {
+        let mut r = resolver();
+        r.define_portal(up(1, 0.0, 0.0), Portal::Discrete(DPoint2::new(3, 4), 2)).unwrap();
+        r.define_portal(up(1, 1.0, 0.0), Portal::Continuous(CPoint2::new(0.5, 0.25), 3)).unwrap();
+        r.define_portal(up(1, 2.0, 0.0), Portal::Nonspecific(2)).unwrap();
+        assert_eq!(r.resolve(&up(1, 0.0, 0.0)), Some(Portal::Discrete(DPoint2::new(3, 4), 2)));
+        assert_eq!(r.resolve(&up(1, 1.0, 0.0)).and_then(|p| p.arrival_point()), Some(up(3, 0.5, 0.25)));
+        assert_eq!(r.resolve(&up(1, 2.0, 0.0)).map(|p| p.get_lid()), Some(2));
+        assert_eq!(r.resolve(&up(1, 2.0, 0.0)).and_then(|p| p.arrival_point()), None);
+        assert_eq!(r.resolve(&up(2, 0.0, 0.0)), None);
+        // -0.0 is the same place as 0.0
+        assert!(r.resolve(&up(1, -0.0, -0.0)).is_some());
+        match r.define_portal(up(1, 5.0, 5.0), Portal::Nonspecific(9)) {
+            Err(PortalError::UnknownLocation(9)) => (),
+            other => panic!("expected an unknown location, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bidirectional_portals_lead_back() {
+        let mut r = resolver();
+        r.define_bidirectional(up(1, 2.0, 3.0), Portal::Discrete(DPoint2::new(7, 8), 2)).unwrap();
+        assert_eq!(r.resolve(&up(2, 7.0, 8.0)), Some(Portal::Continuous(CPoint2::new(2.0, 3.0), 1)));
+        match r.define_bidirectional(up(1, 0.0, 0.0), Portal::Nonspecific(2)) {
+            Err(PortalError::NotReversible(_)) => (),
+            other => panic!("expected not reversible, got {:?}", other),
+        }
+        assert_eq!(r.resolve(&up(1, 0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn bidirectional_portals_are_all_or_nothing() {
+        let mut r = resolver();
+        r.define_portal(up(2, 7.0, 8.0), Portal::Nonspecific(3)).unwrap();
+        // the way back is taken
+        match r.define_bidirectional(up(1, 2.0, 3.0), Portal::Discrete(DPoint2::new(7, 8), 2)) {
+            Err(PortalError::AlreadyDefined(p)) => assert_eq!(p, up(2, 7.0, 8.0)),
+            other => panic!("expected already defined, got {:?}", other),
+        }
+        assert_eq!(r.resolve(&up(1, 2.0, 3.0)), None);
+        assert_eq!(r.resolve(&up(2, 7.0, 8.0)), Some(Portal::Nonspecific(3)));
+        // the way back leads somewhere unknown
+        match r.define_bidirectional(up(1, 2.0, 3.0), Portal::Continuous(CPoint2::new(1.0, 1.0), 8)) {
+            Err(PortalError::UnknownLocation(8)) => (),
+            other => panic!("expected an unknown location, got {:?}", other),
+        }
+        assert_eq!(r.resolve(&up(1, 2.0, 3.0)), None);
+        // and a portal can't be its own way back
+        match r.define_bidirectional(up(1, 4.0, 4.0), Portal::Discrete(DPoint2::new(4, 4), 1)) {
+            Err(PortalError::AlreadyDefined(_)) => (),
+            other => panic!("expected already defined, got {:?}", other),
+        }
+        assert_eq!(r.resolve(&up(1, 4.0, 4.0)), None);
+    }
+
+    #[test]
+    fn removed_portals_are_gone() {
+        let mut r = resolver();
+        r.define_bidirectional(up(1, 2.0, 3.0), Portal::Discrete(DPoint2::new(7, 8), 2)).unwrap();
+        assert_eq!(r.remove_portal(&up(1, 2.0, 3.0)), Some(Portal::Discrete(DPoint2::new(7, 8), 2)));
+        assert_eq!(r.remove_portal(&up(1, 2.0, 3.0)), None);
+        assert_eq!(r.resolve(&up(1, 2.0, 3.0)), None);
+        // only that half
+        assert!(r.resolve(&up(2, 7.0, 8.0)).is_some());
+        // and the entry point is free again
+        r.define_portal(up(1, 2.0, 3.0), Portal::Nonspecific(3)).unwrap();
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut r = resolver();
+        r.define_bidirectional(up(1, 2.0, 3.0), Portal::Discrete(DPoint2::new(7, 8), 2)).unwrap();
+        r.define_portal(up(3, 0.5, 0.5), Portal::Nonspecific(1)).unwrap();
+        r.define_portal(up(2, 1.5, 0.0), Portal::Continuous(CPoint2::new(9.0, 9.5), 3)).unwrap();
+        let mut saved = vec![];
+        r.save(&mut saved).unwrap();
+        let loaded = PortalResolver::load(&saved[..]).unwrap();
+        for lid in 1..4 {
+            assert!(loaded.knows_location(lid));
+        }
+        assert!(!loaded.knows_location(4));
+        for entry in [up(1, 2.0, 3.0), up(2, 7.0, 8.0), up(3, 0.5, 0.5), up(2, 1.5, 0.0), up(1, 0.0, 0.0)].iter() {
+            assert_eq!(loaded.resolve(entry), r.resolve(entry));
+        }
+        let mut again = vec![];
+        loaded.save(&mut again).unwrap();
+        assert_eq!(again, saved);
+    }
+}
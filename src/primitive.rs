@@ -0,0 +1,58 @@
+// A primitive is the small, seeded description something is generated from.
+// state changes are kept as diffs on top of it rather than as the generated thing itself,
+// so a save only needs the primitive and the diffs applied since.
+
+pub trait AppliesDiff<D> {
+    fn apply(&mut self, diff: &D);
+}
+
+pub trait DiffFor<T> {
+    fn apply_to(&self, x: &mut T);
+}
+
+impl<T, D> DiffFor<T> for D where T: AppliesDiff<D> {
+    fn apply_to(&self, x: &mut T) {
+        x.apply(self);
+    }
+}
+
+pub trait Primitive<T: AppliesDiff<Diff>, Diff> {
+    // must be deterministic: the same primitive always generates the same T
+    fn generate_new(&self) -> T;
+
+    fn generate_diffed(&self, diffs: &[Diff]) -> T {
+        let mut x = self.generate_new();
+        for d in diffs.iter() {
+            x.apply(d);
+        }
+        x
+    }
+}
+
+// everything needed to rebuild some T as it currently is
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct Diffed<P, D> {
+    primitive: P,
+    diffs: Vec<D>,
+}
+
+impl<P, D> Diffed<P, D> {
+    pub fn new(primitive: P) -> Diffed<P, D> {
+        Diffed {
+            primitive: primitive,
+            diffs: vec![],
+        }
+    }
+
+    pub fn get_primitive(&self) -> &P {&self.primitive}
+    pub fn get_diffs(&self) -> &[D] {&self.diffs}
+
+    pub fn push(&mut self, diff: D) {
+        self.diffs.push(diff);
+    }
+
+    pub fn regenerate<T>(&self) -> T
+    where P: Primitive<T, D>, T: AppliesDiff<D> {
+        self.primitive.generate_diffed(&self.diffs)
+    }
+}
@@ -113,3 +113,59 @@ impl NoiseField {
         sigmoid(sample_tot, self.perlin_units.len() as f32)
     }
 }
+
+// Bridson's poisson-disk sampling. returns points within [0,width)x[0,height)
+// no two of which are closer than min_dist.
+pub fn poisson_disk<R : Rng>(rng : &mut R, width : f32, height : f32, min_dist : f32) -> Vec<CPoint2> {
+    const ATTEMPTS : u32 = 30;
+    assert!(min_dist > 0.0);
+    let bucket_size = min_dist / 2.0f32.sqrt();
+    let buckets_wide = (width / bucket_size).ceil() as usize + 1;
+    let buckets_high = (height / bucket_size).ceil() as usize + 1;
+    // each bucket is small enough to hold at most one point
+    let mut buckets : Vec<Option<usize>> = vec![None ; buckets_wide * buckets_high];
+    let bucket_of = |pt : CPoint2| {
+        ((pt.x / bucket_size) as usize, (pt.y / bucket_size) as usize)
+    };
+
+    let mut points : Vec<CPoint2> = vec![];
+    let mut active : Vec<usize> = vec![];
+    let first = CPoint2::new(rng.gen::<f32>() * width, rng.gen::<f32>() * height);
+    let (bx, by) = bucket_of(first);
+    buckets[by * buckets_wide + bx] = Some(0);
+    points.push(first);
+    active.push(0);
+
+    while !active.is_empty() {
+        let active_index = rng.gen_range(0, active.len());
+        let around = points[active[active_index]];
+        let mut found = false;
+        'attempts: for _ in 0..ATTEMPTS {
+            let angle = rng.gen::<f32>() * ::std::f32::consts::PI * 2.0;
+            let dist = min_dist * (1.0 + rng.gen::<f32>());
+            let candidate = CPoint2::new(around.x + angle.cos() * dist, around.y + angle.sin() * dist);
+            if candidate.x < 0.0 || candidate.y < 0.0 || candidate.x >= width || candidate.y >= height {
+                continue;
+            }
+            let (cx, cy) = bucket_of(candidate);
+            for ny in cy.saturating_sub(2)..(cy+3).min(buckets_high) {
+                for nx in cx.saturating_sub(2)..(cx+3).min(buckets_wide) {
+                    if let Some(other) = buckets[ny * buckets_wide + nx] {
+                        if points[other].dist_to(candidate) < min_dist {
+                            continue 'attempts;
+                        }
+                    }
+                }
+            }
+            buckets[cy * buckets_wide + cx] = Some(points.len());
+            active.push(points.len());
+            points.push(candidate);
+            found = true;
+            break;
+        }
+        if !found {
+            active.swap_remove(active_index);
+        }
+    }
+    points
+}
@@ -0,0 +1,300 @@
+use std::io::{self,Read,Write};
+use std::marker::PhantomData;
+use std::net::{TcpStream,ToSocketAddrs};
+use std::sync::mpsc::{channel,Sender,Receiver};
+use ::serde::Serialize;
+use ::serde::de::DeserializeOwned;
+use ::serde_json;
+use super::primitive::{Diffed,Primitive};
+use super::world::location::{Location,LocationPrimitive,LocationDiff};
+use super::world::interior::{Interior,InteriorPrimitive,InteriorDiff};
+
+/*
+every message travels as one frame:
+    [u32 big-endian length][json Envelope {version, message}]
+
+a frame from a peer speaking another PROTOCOL_VERSION is rejected before
+its message is even looked at.
+*/
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// no legitimate message comes anywhere near this
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(io::Error),
+    Serde(serde_json::Error),
+    VersionMismatch(u32),
+    FrameTooLarge(usize),
+    // the other end hung up
+    Disconnected,
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> ProtocolError {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            ProtocolError::Disconnected
+        } else {
+            ProtocolError::Io(e)
+        }
+    }
+}
+
+impl From<serde_json::Error> for ProtocolError {
+    fn from(e: serde_json::Error) -> ProtocolError {ProtocolError::Serde(e)}
+}
+
+// what a client needs to generate a location itself, instead of being sent its cells
+#[derive(Clone,Serialize,Deserialize)]
+pub enum LocationSource {
+    Zone(Diffed<LocationPrimitive, LocationDiff>),
+    Interior(Diffed<InteriorPrimitive, InteriorDiff>),
+}
+
+pub enum LoadedLocation {
+    Zone(Location),
+    Interior(Interior),
+}
+
+impl LocationSource {
+    pub fn regenerate(&self) -> LoadedLocation {
+        match self {
+            &LocationSource::Zone(ref d) => LoadedLocation::Zone(d.get_primitive().generate_diffed(d.get_diffs())),
+            &LocationSource::Interior(ref d) => LoadedLocation::Interior(d.get_primitive().generate_diffed(d.get_diffs())),
+        }
+    }
+}
+
+impl ::std::fmt::Debug for LocationSource {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        match self {
+            &LocationSource::Zone(ref d) => write!(f, "Zone(.., {} diffs)", d.get_diffs().len()),
+            &LocationSource::Interior(ref d) => write!(f, "Interior({:?}, {} diffs)", d.get_primitive(), d.get_diffs().len()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, M: 'a> {
+    version: u32,
+    message: &'a M,
+}
+
+#[derive(Deserialize)]
+struct RawEnvelope {
+    version: u32,
+    message: serde_json::Value,
+}
+
+pub fn write_frame<W: Write, M: Serialize>(w: &mut W, message: &M) -> Result<(), ProtocolError> {
+    let payload = serde_json::to_vec(&Envelope {version: PROTOCOL_VERSION, message: message})?;
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(payload.len()));
+    }
+    let len = payload.len() as u32;
+    w.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8])?;
+    w.write_all(&payload)?;
+    w.flush()?;
+    Ok(())
+}
+
+pub fn read_frame<R: Read, M: DeserializeOwned>(r: &mut R) -> Result<M, ProtocolError> {
+    let mut len_bytes = [0u8; 4];
+    r.read_exact(&mut len_bytes)?;
+    let len = len_bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+    if len > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(len));
+    }
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+    let raw: RawEnvelope = serde_json::from_slice(&payload)?;
+    if raw.version != PROTOCOL_VERSION {
+        return Err(ProtocolError::VersionMismatch(raw.version));
+    }
+    Ok(serde_json::from_value(raw.message)?)
+}
+
+// one end of a connection. sends Out, receives In
+pub trait Transport<Out, In> {
+    fn send(&mut self, message: &Out) -> Result<(), ProtocolError>;
+    // blocks until a whole message has arrived
+    fn recv(&mut self) -> Result<In, ProtocolError>;
+}
+
+pub struct TcpTransport<Out, In> {
+    stream: TcpStream,
+    phantom: PhantomData<(Out, In)>,
+}
+
+impl<Out, In> TcpTransport<Out, In> {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<TcpTransport<Out, In>, ProtocolError> {
+        Ok(Self::from_stream(TcpStream::connect(addr)?))
+    }
+
+    // eg: a stream accepted by a TcpListener
+    pub fn from_stream(stream: TcpStream) -> TcpTransport<Out, In> {
+        TcpTransport {
+            stream: stream,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Out: Serialize, In: DeserializeOwned> Transport<Out, In> for TcpTransport<Out, In> {
+    fn send(&mut self, message: &Out) -> Result<(), ProtocolError> {
+        write_frame(&mut self.stream, message)
+    }
+
+    fn recv(&mut self) -> Result<In, ProtocolError> {
+        read_frame(&mut self.stream)
+    }
+}
+
+// frames go through a channel instead of a socket, still fully encoded
+pub struct MemoryTransport<Out, In> {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    phantom: PhantomData<(Out, In)>,
+}
+
+pub fn memory_pair<A, B>() -> (MemoryTransport<A, B>, MemoryTransport<B, A>) {
+    let (a_tx, b_rx) = channel();
+    let (b_tx, a_rx) = channel();
+    (
+        MemoryTransport {tx: a_tx, rx: a_rx, phantom: PhantomData},
+        MemoryTransport {tx: b_tx, rx: b_rx, phantom: PhantomData},
+    )
+}
+
+impl<Out: Serialize, In: DeserializeOwned> Transport<Out, In> for MemoryTransport<Out, In> {
+    fn send(&mut self, message: &Out) -> Result<(), ProtocolError> {
+        let mut frame = vec![];
+        write_frame(&mut frame, message)?;
+        self.tx.send(frame).map_err(|_| ProtocolError::Disconnected)
+    }
+
+    fn recv(&mut self) -> Result<In, ProtocolError> {
+        let frame = self.rx.recv().map_err(|_| ProtocolError::Disconnected)?;
+        read_frame(&mut &frame[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::points::{CPoint2,DPoint2};
+    use ::primitive::Diffed;
+    use ::portals::{UniquePoint,Portal,PortalResolver};
+    use ::world::interior::{InteriorPrimitive,Race};
+    use ::server::{Server,ClientMessage,ServerMessage,ServerDiff};
+    use ::entities::EntityDiff;
+    use super::*;
+
+    // a frame around any json, whatever version it claims
+    fn raw_frame(payload: &[u8]) -> Vec<u8> {
+        let len = payload.len() as u32;
+        let mut frame = vec![(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let sent = vec![
+            ClientMessage::UnloadLID(4),
+            ClientMessage::TraversePortal(UniquePoint::new(1, CPoint2::new(2.0, 3.0))),
+        ];
+        let mut bytes = vec![];
+        for m in sent.iter() {
+            write_frame(&mut bytes, m).unwrap();
+        }
+        let mut r = &bytes[..];
+        for m in sent.iter() {
+            let got: ClientMessage = read_frame(&mut r).unwrap();
+            assert_eq!(got, *m);
+        }
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let frame = raw_frame(br#"{"version":7,"message":{"UnloadLID":4}}"#);
+        match read_frame::<_, ClientMessage>(&mut &frame[..]) {
+            Err(ProtocolError::VersionMismatch(7)) => (),
+            other => panic!("expected a version mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let len = MAX_FRAME_LEN as u32 + 1;
+        let header = [(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+        match read_frame::<_, ClientMessage>(&mut &header[..]) {
+            Err(ProtocolError::FrameTooLarge(l)) => assert_eq!(l, MAX_FRAME_LEN + 1),
+            other => panic!("expected a too large frame, got {:?}", other),
+        }
+        let huge = "x".repeat(MAX_FRAME_LEN);
+        let mut bytes = vec![];
+        match write_frame(&mut bytes, &huge) {
+            Err(ProtocolError::FrameTooLarge(_)) => assert!(bytes.is_empty()),
+            other => panic!("expected a too large frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn truncated_frames_are_a_disconnect() {
+        let mut frame = vec![];
+        write_frame(&mut frame, &ClientMessage::UnloadLID(4)).unwrap();
+        for cut in [2, 4, frame.len() - 1].iter() {
+            match read_frame::<_, ClientMessage>(&mut &frame[..*cut]) {
+                Err(ProtocolError::Disconnected) => (),
+                other => panic!("expected a disconnect at {}, got {:?}", cut, other),
+            }
+        }
+    }
+
+    #[test]
+    fn client_and_server_over_memory_pair() {
+        let source = |lid| LocationSource::Interior(Diffed::new(
+            InteriorPrimitive::new(lid, 0.5, Race::Human, 0.1, UniquePoint::new(1, CPoint2::new(0.0, 0.0)))
+        ));
+        let mut server = Server::new(PortalResolver::new());
+        server.add_location(1, source(1));
+        server.add_location(2, source(2));
+        let door = UniquePoint::new(1, CPoint2::new(3.0, 3.0));
+        server.get_resolver_mut().define_bidirectional(door, Portal::Discrete(DPoint2::new(7, 7), 2)).unwrap();
+        let (client_id, _) = server.connect(door).unwrap();
+
+        let (mut client, mut server_end) = memory_pair::<ClientMessage, ServerMessage>();
+        client.send(&ClientMessage::TraversePortal(door)).unwrap();
+        let request = server_end.recv().unwrap();
+        assert_eq!(request, ClientMessage::TraversePortal(door));
+        for (to, m) in server.handle(client_id, request).unwrap() {
+            assert_eq!(to, client_id);
+            server_end.send(&m).unwrap();
+        }
+
+        // the new location arrives as something to generate, then the move into it
+        match client.recv().unwrap() {
+            ServerMessage::LoadLID(2, source) => match source.regenerate() {
+                LoadedLocation::Interior(_) => (),
+                LoadedLocation::Zone(_) => panic!("expected an interior"),
+            },
+            other => panic!("expected LoadLID(2), got {:?}", other),
+        }
+        match client.recv().unwrap() {
+            // sent on the location being left, to whoever else is there
+            ServerMessage::ApplyDiff(1, ServerDiff::Entity(EntityDiff::Move(_, to))) => {
+                assert_eq!((to.lid, to.cell), (2, DPoint2::new(7, 7)))
+            },
+            other => panic!("expected a move, got {:?}", other),
+        }
+
+        drop(server_end);
+        match client.recv() {
+            Err(ProtocolError::Disconnected) => (),
+            other => panic!("expected a disconnect, got {:?}", other),
+        }
+    }
+}
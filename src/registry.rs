@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use ::rand::{SeedableRng,Rng,Isaac64Rng};
+use super::LocationID;
+use super::world::WorldPrimitive;
+
+/*
+LocationIDs are never handed out from a counter. each one is derived from its
+parent's ID and its index within that parent, so every server (and client) that
+knows a WorldPrimitive arrives at the same IDs for the same places.
+
+    world     <- super_seed
+    zone i    <- (world lid, i)
+    interior  <- (zone or interior lid, i)
+*/
+
+const WORLD_TAG: u64 = 1;
+const ZONE_TAG: u64 = 2;
+const INTERIOR_TAG: u64 = 3;
+const SEED_TAG: u64 = 4;
+
+fn derive(parent: u64, tag: u64, index: u64) -> u64 {
+    Isaac64Rng::from_seed(&[parent, tag, index]).gen()
+}
+
+// the seed to generate the location's primitive with. unrelated to the ID itself
+pub fn seed_of(lid: LocationID) -> u64 {
+    derive(lid, SEED_TAG, 0)
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,Serialize,Deserialize)]
+pub enum LocationKind {
+    World,
+    Zone(usize),
+    Interior(usize),
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    UnknownLocation(LocationID),
+    // eg: a zone whose parent is not a world
+    WrongParentKind(LocationID, LocationKind),
+    // two different places derived the same ID
+    Collision(LocationID),
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind: LocationKind,
+    parent: Option<LocationID>,
+    children: Vec<LocationID>,
+}
+
+#[derive(Debug)]
+pub struct LocationRegistry {
+    entries: HashMap<LocationID, Entry>,
+}
+
+impl LocationRegistry {
+    pub fn new() -> LocationRegistry {
+        LocationRegistry {
+            entries: HashMap::new(),
+        }
+    }
+
+    // registering the same location twice is fine, and yields the same ID
+    fn register(&mut self, lid: LocationID, kind: LocationKind, parent: Option<LocationID>) -> Result<LocationID, RegistryError> {
+        if let Some(existing) = self.entries.get(&lid) {
+            return if existing.kind == kind && existing.parent == parent {
+                Ok(lid)
+            } else {
+                Err(RegistryError::Collision(lid))
+            }
+        }
+        if let Some(p) = parent {
+            self.entries.get_mut(&p)
+            .ok_or(RegistryError::UnknownLocation(p))?
+            .children.push(lid);
+        }
+        self.entries.insert(lid, Entry {kind: kind, parent: parent, children: vec![]});
+        Ok(lid)
+    }
+
+    pub fn register_world(&mut self, wp: &WorldPrimitive) -> Result<LocationID, RegistryError> {
+        // the same seed with its zones connected is a different world
+        let variant = if wp.has_connected_zones() {1} else {0};
+        let lid = derive(wp.get_super_seed(), WORLD_TAG, variant);
+        self.register(lid, LocationKind::World, None)
+    }
+
+    pub fn register_zone(&mut self, world: LocationID, zone_index: usize) -> Result<LocationID, RegistryError> {
+        match self.kind_of(world) {
+            Some(LocationKind::World) => (),
+            Some(k) => return Err(RegistryError::WrongParentKind(world, k)),
+            None => return Err(RegistryError::UnknownLocation(world)),
+        }
+        let lid = derive(world, ZONE_TAG, zone_index as u64);
+        self.register(lid, LocationKind::Zone(zone_index), Some(world))
+    }
+
+    // interiors live in zones, or in other interiors
+    pub fn register_interior(&mut self, parent: LocationID, index: usize) -> Result<LocationID, RegistryError> {
+        match self.kind_of(parent) {
+            Some(LocationKind::World) => return Err(RegistryError::WrongParentKind(parent, LocationKind::World)),
+            Some(_) => (),
+            None => return Err(RegistryError::UnknownLocation(parent)),
+        }
+        let lid = derive(parent, INTERIOR_TAG, index as u64);
+        self.register(lid, LocationKind::Interior(index), Some(parent))
+    }
+
+    pub fn contains(&self, lid: LocationID) -> bool {
+        self.entries.contains_key(&lid)
+    }
+
+    pub fn kind_of(&self, lid: LocationID) -> Option<LocationKind> {
+        self.entries.get(&lid).map(|e| e.kind)
+    }
+
+    pub fn parent_of(&self, lid: LocationID) -> Option<LocationID> {
+        self.entries.get(&lid).and_then(|e| e.parent)
+    }
+
+    pub fn children_of(&self, lid: LocationID) -> &[LocationID] {
+        self.entries.get(&lid).map(|e| &e.children[..]).unwrap_or(&[])
+    }
+
+    // the world a location ultimately belongs to. a world belongs to itself
+    pub fn world_of(&self, lid: LocationID) -> Option<LocationID> {
+        let mut at = lid;
+        loop {
+            match self.entries.get(&at) {
+                Some(&Entry {parent: Some(p), ..}) => at = p,
+                Some(&Entry {kind: LocationKind::World, ..}) => return Some(at),
+                _ => return None,
+            }
+        }
+    }
+
+    // only the interiors directly inside `lid`
+    pub fn interiors_of(&self, lid: LocationID) -> Vec<LocationID> {
+        self.children_of(lid).iter()
+        .filter(|c| match self.kind_of(**c) {
+            Some(LocationKind::Interior(_)) => true,
+            _ => false,
+        })
+        .cloned()
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a world with two zones, one with an interior that has an interior of its own
+    fn hierarchy(r: &mut LocationRegistry) -> (LocationID, LocationID, LocationID, LocationID, LocationID) {
+        let w = r.register_world(&WorldPrimitive::new(7, 0.5, 0.5)).unwrap();
+        let z0 = r.register_zone(w, 0).unwrap();
+        let z1 = r.register_zone(w, 1).unwrap();
+        let i = r.register_interior(z1, 0).unwrap();
+        let ii = r.register_interior(i, 0).unwrap();
+        (w, z0, z1, i, ii)
+    }
+
+    #[test]
+    fn ids_are_the_same_every_run() {
+        // written down once. if these change, every saved game's IDs do too
+        let mut r = LocationRegistry::new();
+        let w = r.register_world(&WorldPrimitive::new(7, 0.5, 0.5)).unwrap();
+        let z = r.register_zone(w, 2).unwrap();
+        let i = r.register_interior(z, 0).unwrap();
+        assert_eq!(w, 12673107037076229484);
+        assert_eq!(z, 1964565877842394925);
+        assert_eq!(i, 7934472963134296321);
+        assert_eq!(seed_of(z), 7931178689954955377);
+
+        // and in any other registry, in any order
+        let mut other = LocationRegistry::new();
+        let w2 = other.register_world(&WorldPrimitive::new(7, 0.5, 0.5)).unwrap();
+        other.register_zone(w2, 5).unwrap();
+        assert_eq!(other.register_zone(w2, 2).unwrap(), z);
+        assert_eq!(other.register_zone(w2, 2).unwrap(), z);
+        assert!(other.register_world(&WorldPrimitive::new(7, 0.5, 0.5).with_connected_zones()).unwrap() != w);
+    }
+
+    #[test]
+    fn walks_the_hierarchy() {
+        let mut r = LocationRegistry::new();
+        let (w, z0, z1, i, ii) = hierarchy(&mut r);
+        assert_eq!(r.parent_of(w), None);
+        assert_eq!(r.parent_of(z1), Some(w));
+        assert_eq!(r.parent_of(ii), Some(i));
+        assert_eq!(r.children_of(w), &[z0, z1]);
+        assert!(r.children_of(z0).is_empty());
+        assert!(r.children_of(12345).is_empty());
+        for lid in [w, z0, z1, i, ii].iter() {
+            assert_eq!(r.world_of(*lid), Some(w));
+        }
+        assert_eq!(r.world_of(12345), None);
+        assert_eq!(r.interiors_of(z1), vec![i]);
+        assert_eq!(r.interiors_of(i), vec![ii]);
+        assert!(r.interiors_of(w).is_empty());
+        assert_eq!(r.kind_of(i), Some(LocationKind::Interior(0)));
+    }
+
+    #[test]
+    fn rejects_bad_parents_and_clashes() {
+        let mut r = LocationRegistry::new();
+        let (w, z0, _, i, _) = hierarchy(&mut r);
+        match r.register_interior(w, 0) {
+            Err(RegistryError::WrongParentKind(lid, LocationKind::World)) => assert_eq!(lid, w),
+            other => panic!("expected a wrong parent, got {:?}", other),
+        }
+        match r.register_zone(z0, 0) {
+            Err(RegistryError::WrongParentKind(lid, LocationKind::Zone(0))) => assert_eq!(lid, z0),
+            other => panic!("expected a wrong parent, got {:?}", other),
+        }
+        match r.register_zone(12345, 0) {
+            Err(RegistryError::UnknownLocation(12345)) => (),
+            other => panic!("expected an unknown location, got {:?}", other),
+        }
+        // the same ID for something else
+        match r.register(i, LocationKind::Zone(3), Some(w)) {
+            Err(RegistryError::Collision(lid)) => assert_eq!(lid, i),
+            other => panic!("expected a collision, got {:?}", other),
+        }
+        match r.register(z0, LocationKind::Zone(0), Some(i)) {
+            Err(RegistryError::Collision(lid)) => assert_eq!(lid, z0),
+            other => panic!("expected a collision, got {:?}", other),
+        }
+        // and nothing changed
+        assert_eq!(r.kind_of(i), Some(LocationKind::Interior(0)));
+        assert_eq!(r.parent_of(z0), Some(w));
+    }
+}
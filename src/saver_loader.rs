@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::fs::{self,File,OpenOptions};
+use std::io::{self,BufRead,BufReader,BufWriter,Write};
+use std::path::{Path,PathBuf};
+use ::serde_json;
+use super::LocationID;
+use super::points::DPoint2;
+use super::primitive::{Primitive,AppliesDiff};
+use super::world::Material;
+use super::world::grid::TotalGrid;
+use super::world::location::{Location,LocationPrimitive,LocationDiff};
+use super::world::placement::Placements;
+
+/*
+Each location gets two files in the store's directory:
+    <lid>.log       one LocationDiff per line, only ever appended to (until compacted)
+    <lid>.snapshot  the mutable state of the location after the first `log_len` lines of the log
+
+loading = generate from the primitive, restore the snapshot, replay the rest of the log.
+this always ends in the same state as replaying the whole log from scratch.
+
+the snapshot also holds a checksum of the lines it covers, and those lines are only
+hashed, never parsed, when loading. compacting rewrites both files, so a crash in
+between leaves a snapshot that doesn't match the log. loading then fails with
+StaleSnapshot rather than replaying the wrong lines. the log alone is still
+complete, so `replay_all` recovers from that.
+*/
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Serde(serde_json::Error),
+    // the log has a line that isn't a LocationDiff. lines count from 1
+    CorruptLog(LocationID, usize),
+    // the snapshot covers lines the log doesn't (or no longer) have
+    StaleSnapshot(LocationID),
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> StoreError {StoreError::Io(e)}
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> StoreError {StoreError::Serde(e)}
+}
+
+#[derive(Serialize,Deserialize)]
+struct LocationSnapshot {
+    log_len: usize,
+    // of the first `log_len` lines
+    log_checksum: u64,
+    materials: TotalGrid<Material>,
+    objects: Placements,
+}
+
+pub struct SaverLoader {
+    dir: PathBuf,
+    snapshot_every: usize,
+    since_snapshot: HashMap<LocationID, usize>,
+}
+
+// FNV-1a over the lines, each with its newline. hand-rolled to stay the same
+// across compilers, unlike std's hashers
+fn checksum(lines: &[(usize, String)]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &(_, ref line) in lines.iter() {
+        for b in line.bytes().chain(Some(b'\n')) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+// drops every diff whose effect is certainly overwritten by a later one.
+// replaying the result from ANY starting state gives the same state as replaying all of `diffs`
+fn compacted(diffs: &[LocationDiff]) -> Vec<LocationDiff> {
+    let mut last_material: HashMap<DPoint2, usize> = HashMap::new();
+    let mut last_removal: HashMap<DPoint2, usize> = HashMap::new();
+    for (i, d) in diffs.iter().enumerate() {
+        match d {
+            &LocationDiff::SetMaterial(cell, _) => {last_material.insert(cell, i);},
+            &LocationDiff::RemoveObject(cell) => {last_removal.insert(cell, i);},
+            &LocationDiff::PlaceObject(..) => (),
+        }
+    }
+    // once a cell holds something, placing there again is a no-op. so only the
+    // first placement after the last removal can matter
+    let mut first_placement: HashMap<DPoint2, usize> = HashMap::new();
+    for (i, d) in diffs.iter().enumerate() {
+        if let &LocationDiff::PlaceObject(cell, _) = d {
+            let after_removal = last_removal.get(&cell).map(|r| i > *r).unwrap_or(true);
+            if after_removal && !first_placement.contains_key(&cell) {
+                first_placement.insert(cell, i);
+            }
+        }
+    }
+    diffs.iter().enumerate()
+    .filter(|&(i, d)| {
+        let cell = d.get_cell();
+        match d {
+            &LocationDiff::SetMaterial(..) => last_material.get(&cell) == Some(&i),
+            &LocationDiff::RemoveObject(_) => last_removal.get(&cell) == Some(&i),
+            &LocationDiff::PlaceObject(..) => first_placement.get(&cell) == Some(&i),
+        }
+    })
+    .map(|(_, d)| *d)
+    .collect()
+}
+
+impl SaverLoader {
+    // a snapshot is written after every `snapshot_every` recorded diffs of a location
+    pub fn new(dir: &Path, snapshot_every: usize) -> Result<SaverLoader, StoreError> {
+        assert!(snapshot_every > 0);
+        fs::create_dir_all(dir)?;
+        Ok(SaverLoader {
+            dir: dir.to_path_buf(),
+            snapshot_every: snapshot_every,
+            since_snapshot: HashMap::new(),
+        })
+    }
+
+    fn log_path(&self, lid: LocationID) -> PathBuf {
+        self.dir.join(format!("{}.log", lid))
+    }
+
+    fn snapshot_path(&self, lid: LocationID) -> PathBuf {
+        self.dir.join(format!("{}.snapshot", lid))
+    }
+
+    // the non-empty lines of the log, unparsed, with their line numbers
+    fn read_log_lines(&self, lid: LocationID) -> Result<Vec<(usize, String)>, StoreError> {
+        let file = match File::open(self.log_path(lid)) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = vec![];
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {continue}
+            lines.push((i+1, line));
+        }
+        Ok(lines)
+    }
+
+    fn parse_lines(lid: LocationID, lines: &[(usize, String)]) -> Result<Vec<LocationDiff>, StoreError> {
+        lines.iter()
+        .map(|&(n, ref line)| serde_json::from_str(line).map_err(|_| StoreError::CorruptLog(lid, n)))
+        .collect()
+    }
+
+    fn read_log(&self, lid: LocationID) -> Result<Vec<LocationDiff>, StoreError> {
+        Self::parse_lines(lid, &self.read_log_lines(lid)?)
+    }
+
+    // how many of `lines` the snapshot covers, checking that it really covers them
+    fn covered_by(lid: LocationID, snapshot: &LocationSnapshot, lines: &[(usize, String)]) -> Result<usize, StoreError> {
+        if snapshot.log_len > lines.len() || checksum(&lines[..snapshot.log_len]) != snapshot.log_checksum {
+            return Err(StoreError::StaleSnapshot(lid))
+        }
+        Ok(snapshot.log_len)
+    }
+
+    fn read_snapshot(&self, lid: LocationID) -> Result<Option<LocationSnapshot>, StoreError> {
+        match File::open(self.snapshot_path(lid)) {
+            Ok(f) => Ok(Some(serde_json::from_reader(BufReader::new(f))?)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // writes to a temporary file first so a crash never leaves half a file behind
+    fn replace_file<F>(path: &Path, write_contents: F) -> Result<(), StoreError>
+    where F: FnOnce(&mut BufWriter<File>) -> Result<(), StoreError> {
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            write_contents(&mut writer)?;
+            writer.flush()?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    // `current` must be the location with `diff` (and every diff before it) already applied
+    pub fn record(&mut self, lid: LocationID, diff: &LocationDiff, current: &Location) -> Result<(), StoreError> {
+        {
+            let mut file = OpenOptions::new().create(true).append(true).open(self.log_path(lid))?;
+            let mut line = serde_json::to_string(diff)?;
+            line.push('\n');
+            file.write_all(line.as_bytes())?;
+        }
+        let since = match self.since_snapshot.get(&lid) {
+            Some(&x) => x + 1,
+            None => {
+                let lines = self.read_log_lines(lid)?;
+                let covered = match self.read_snapshot(lid)? {
+                    Some(snapshot) => Self::covered_by(lid, &snapshot, &lines)?,
+                    None => 0,
+                };
+                lines.len() - covered
+            },
+        };
+        self.since_snapshot.insert(lid, since);
+        if since >= self.snapshot_every {
+            self.snapshot(lid, current)?;
+        }
+        Ok(())
+    }
+
+    // `current` must reflect the whole log as it is now
+    pub fn snapshot(&mut self, lid: LocationID, current: &Location) -> Result<(), StoreError> {
+        let lines = self.read_log_lines(lid)?;
+        let snapshot = LocationSnapshot {
+            log_len: lines.len(),
+            log_checksum: checksum(&lines),
+            materials: current.get_materials().clone(),
+            objects: current.get_objects().clone(),
+        };
+        Self::replace_file(&self.snapshot_path(lid), |w| {
+            serde_json::to_writer(w, &snapshot)?;
+            Ok(())
+        })?;
+        self.since_snapshot.insert(lid, 0);
+        Ok(())
+    }
+
+    pub fn load(&mut self, lid: LocationID, prim: &LocationPrimitive) -> Result<Location, StoreError> {
+        let lines = self.read_log_lines(lid)?;
+        let mut loc = prim.generate_new();
+        let covered = match self.read_snapshot(lid)? {
+            Some(snapshot) => {
+                let covered = Self::covered_by(lid, &snapshot, &lines)?;
+                loc.restore(snapshot.materials, snapshot.objects);
+                covered
+            },
+            None => 0,
+        };
+        let diffs = Self::parse_lines(lid, &lines[covered..])?;
+        for d in diffs.iter() {
+            loc.apply(d);
+        }
+        self.since_snapshot.insert(lid, diffs.len());
+        Ok(loc)
+    }
+
+    // ignores any snapshot. slow, but the reference for what `load` must return
+    pub fn replay_all(&self, lid: LocationID, prim: &LocationPrimitive) -> Result<Location, StoreError> {
+        Ok(prim.generate_diffed(&self.read_log(lid)?))
+    }
+
+    // rewrites the log without superseded diffs. the parts before and after the
+    // snapshot are compacted separately so that the snapshot stays valid
+    pub fn compact(&mut self, lid: LocationID) -> Result<(), StoreError> {
+        let lines = self.read_log_lines(lid)?;
+        let snapshot = self.read_snapshot(lid)?;
+        let covered = match snapshot {
+            Some(ref snapshot) => Self::covered_by(lid, snapshot, &lines)?,
+            None => 0,
+        };
+        let diffs = Self::parse_lines(lid, &lines)?;
+        let before = compacted(&diffs[..covered]);
+        let after = compacted(&diffs[covered..]);
+        let mut new_lines = vec![];
+        for d in before.iter().chain(after.iter()) {
+            new_lines.push((new_lines.len() + 1, serde_json::to_string(d)?));
+        }
+
+        Self::replace_file(&self.log_path(lid), |w| {
+            for &(_, ref line) in new_lines.iter() {
+                w.write_all(line.as_bytes())?;
+                w.write_all(b"\n")?;
+            }
+            Ok(())
+        })?;
+        if let Some(mut snapshot) = snapshot {
+            snapshot.log_len = before.len();
+            snapshot.log_checksum = checksum(&new_lines[..before.len()]);
+            Self::replace_file(&self.snapshot_path(lid), |w| {
+                serde_json::to_writer(w, &snapshot)?;
+                Ok(())
+            })?;
+        }
+        self.since_snapshot.insert(lid, after.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use ::rand::{SeedableRng,Rng,Isaac64Rng};
+    use ::points::DPoint2;
+    use ::primitive::{Primitive,AppliesDiff};
+    use ::world::{World,WorldPrimitive,Material};
+    use ::world::location::{LocationPrimitive,LocationDiff};
+    use ::world::placement::ObjectKind;
+    use super::{SaverLoader,StoreError};
+
+    fn fresh_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("saver_loader_{}_{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn primitive() -> LocationPrimitive {
+        World::new(WorldPrimitive::new(3, 0.5, 0.5)).location_primitive(0, 7).unwrap()
+    }
+
+    // edits crowded onto a few cells, so that compaction has plenty to drop
+    fn random_diff<R: Rng>(rng: &mut R) -> LocationDiff {
+        let cell = DPoint2::new(rng.gen_range(0, 4), rng.gen_range(0, 4));
+        match rng.gen_range(0, 3) {
+            0 => LocationDiff::SetMaterial(cell, *rng.choose(&[Material::Water, Material::Sand, Material::Rock]).unwrap()),
+            1 => LocationDiff::RemoveObject(cell),
+            _ => LocationDiff::PlaceObject(cell, *rng.choose(&[ObjectKind::Tree, ObjectKind::Boulder, ObjectKind::Ruin]).unwrap()),
+        }
+    }
+
+    #[test]
+    fn load_matches_replay_all() {
+        let dir = fresh_dir("replay");
+        let prim = primitive();
+        let mut rng = Isaac64Rng::from_seed(&[1]);
+        let mut store = SaverLoader::new(&dir, 5).unwrap();
+        let mut loc = prim.generate_new();
+        for i in 0..120 {
+            let d = random_diff(&mut rng);
+            loc.apply(&d);
+            store.record(9, &d, &loc).unwrap();
+            if i % 17 == 0 {store.snapshot(9, &loc).unwrap()}
+            if i % 13 == 0 {store.compact(9).unwrap()}
+            if i % 10 == 0 {
+                let mut reopened = SaverLoader::new(&dir, 5).unwrap();
+                assert!(reopened.load(9, &prim).unwrap() == loc);
+                assert!(reopened.replay_all(9, &prim).unwrap() == loc);
+            }
+        }
+        store.compact(9).unwrap();
+        assert!(store.load(9, &prim).unwrap() == loc);
+        assert!(store.replay_all(9, &prim).unwrap() == loc);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshot_out_of_step_with_log_is_an_error() {
+        let dir = fresh_dir("stale");
+        let prim = primitive();
+        let mut store = SaverLoader::new(&dir, 1000).unwrap();
+        let mut loc = prim.generate_new();
+        for m in [Material::Water, Material::Sand, Material::Rock].iter() {
+            let d = LocationDiff::SetMaterial(DPoint2::new(1, 1), *m);
+            loc.apply(&d);
+            store.record(9, &d, &loc).unwrap();
+        }
+        store.snapshot(9, &loc).unwrap();
+        let old_snapshot = fs::read(dir.join("9.snapshot")).unwrap();
+
+        // as if compaction crashed after rewriting the log
+        store.compact(9).unwrap();
+        fs::write(dir.join("9.snapshot"), &old_snapshot).unwrap();
+        match store.load(9, &prim) {
+            Err(StoreError::StaleSnapshot(9)) => (),
+            other => panic!("expected a stale snapshot, got {:?}", other.map(|_| ())),
+        }
+        // the log alone still has everything
+        assert!(store.replay_all(9, &prim).unwrap() == loc);
+
+        // a snapshot covering more than the log has
+        fs::write(dir.join("9.log"), "").unwrap();
+        match store.load(9, &prim) {
+            Err(StoreError::StaleSnapshot(9)) => (),
+            other => panic!("expected a stale snapshot, got {:?}", other.map(|_| ())),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
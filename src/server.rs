@@ -0,0 +1,485 @@
+use std::collections::{HashMap,HashSet};
+use super::{LocationID,EntityID,CPoint2};
+use super::entities::{EntityStore,EntityDiff,EntityKind,EntityPosition};
+use super::primitive::AppliesDiff;
+use super::portals::{UniquePoint,Portal,PortalResolver};
+use super::world::location::LocationDiff;
+use super::protocol::LocationSource;
+
+/*
+Server needs a list of ROOMS
+server needs a list of WORLDS
+server needs to track which rooms each client does and doesn't have loaded
+
+client                                          server
+  |                                                |
+write(x)      ==TraversePortal(UniquePoint)==>  let x = read().unique_point
+  |                                             let out = portal_resolver.resolve(x)
+read()      <==LoadLID(out.lid)====             write(1)
+read()    <==ApplyDiff(Move(you,out.cout))  write(2)
+
+The server never touches a socket. every call returns the messages it wants
+sent, addressed to clients. whoever owns the connections delivers them.
+*/
+
+pub type ClientID = u64;
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum ClientMessage {
+    TraversePortal(UniquePoint),
+    EditLocation(LocationID, LocationDiff),
+    UnloadLID(LocationID),
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum ServerDiff {
+    Entity(EntityDiff),
+    Location(LocationDiff),
+}
+
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub enum ServerMessage {
+    // the client should generate this location from its source and keep it loaded
+    LoadLID(LocationID, LocationSource),
+    // the client's entity for the rest of the session
+    YouAre(EntityID),
+    ApplyDiff(LocationID, ServerDiff),
+}
+
+pub type Outgoing = Vec<(ClientID, ServerMessage)>;
+
+#[derive(Debug)]
+pub enum ServerError {
+    UnknownClient(ClientID),
+    // the server has no source for this location
+    UnknownLocation(LocationID),
+    // eg: a LocationDiff for an interior
+    WrongLocationKind(LocationID),
+    NoSuchPortal(UniquePoint),
+    // clients can only use portals in the location they are currently in
+    NotInLocation(ClientID, LocationID),
+    NotLoaded(ClientID, LocationID),
+    // a Nonspecific portal into a location without a spawn point
+    NoArrivalPoint(LocationID),
+}
+
+#[derive(Debug)]
+struct ClientSession {
+    avatar: EntityID,
+    loaded: HashSet<LocationID>,
+}
+
+#[derive(Debug)]
+pub struct Server {
+    resolver: PortalResolver,
+    sources: HashMap<LocationID, LocationSource>,
+    spawn_points: HashMap<LocationID, CPoint2>,
+    sessions: HashMap<ClientID, ClientSession>,
+    entities: EntityStore,
+    next_client: ClientID,
+}
+
+impl Server {
+    pub fn new(resolver: PortalResolver) -> Server {
+        Server {
+            resolver: resolver,
+            sources: HashMap::new(),
+            spawn_points: HashMap::new(),
+            sessions: HashMap::new(),
+            entities: EntityStore::new(),
+            next_client: 0,
+        }
+    }
+
+    pub fn get_resolver(&self) -> &PortalResolver {&self.resolver}
+    pub fn get_resolver_mut(&mut self) -> &mut PortalResolver {&mut self.resolver}
+    pub fn get_entities(&self) -> &EntityStore {&self.entities}
+
+    // makes the location available to clients (and to portals)
+    pub fn add_location(&mut self, lid: LocationID, source: LocationSource) {
+        self.resolver.register_location(lid);
+        self.sources.insert(lid, source);
+    }
+
+    pub fn get_source(&self, lid: LocationID) -> Option<&LocationSource> {
+        self.sources.get(&lid)
+    }
+
+    fn load_message(&self, lid: LocationID) -> Result<ServerMessage, ServerError> {
+        match self.sources.get(&lid) {
+            Some(source) => Ok(ServerMessage::LoadLID(lid, source.clone())),
+            None => Err(ServerError::UnknownLocation(lid)),
+        }
+    }
+
+    // where Nonspecific portals into `lid` drop you off
+    pub fn set_spawn_point(&mut self, lid: LocationID, pt: CPoint2) {
+        self.spawn_points.insert(lid, pt);
+    }
+
+    pub fn connect(&mut self, spawn: UniquePoint) -> Result<(ClientID, Outgoing), ServerError> {
+        let load = self.load_message(spawn.get_lid())?;
+        let client = self.next_client;
+        self.next_client += 1;
+        let (avatar, spawn_diff) = self.entities.spawn(
+            EntityKind::Player,
+            EntityPosition::new(spawn.get_lid(), spawn.get_c_pt().floor()),
+        );
+
+        let mut loaded = HashSet::new();
+        loaded.insert(spawn.get_lid());
+        self.sessions.insert(client, ClientSession {avatar: avatar, loaded: loaded});
+
+        let mut out = vec![
+            (client, load),
+            (client, ServerMessage::YouAre(avatar)),
+        ];
+        out.extend(self.introduce_occupants(client, spawn.get_lid()));
+        out.extend(self.broadcast_diff(spawn.get_lid(), ServerDiff::Entity(spawn_diff)));
+        Ok((client, out))
+    }
+
+    pub fn disconnect(&mut self, client: ClientID) -> Result<Outgoing, ServerError> {
+        let session = self.sessions.remove(&client).ok_or(ServerError::UnknownClient(client))?;
+        Ok(self.apply_entity_diff(EntityDiff::Despawn(session.avatar)))
+    }
+
+    pub fn is_loaded_by(&self, client: ClientID, lid: LocationID) -> bool {
+        self.sessions.get(&client).map(|s| s.loaded.contains(&lid)).unwrap_or(false)
+    }
+
+    pub fn loaded_by(&self, client: ClientID) -> Option<&HashSet<LocationID>> {
+        self.sessions.get(&client).map(|s| &s.loaded)
+    }
+
+    pub fn avatar_of(&self, client: ClientID) -> Option<EntityID> {
+        self.sessions.get(&client).map(|s| s.avatar)
+    }
+
+    pub fn position_of(&self, client: ClientID) -> Option<EntityPosition> {
+        self.avatar_of(client).and_then(|a| self.entities.position_of(a))
+    }
+
+    // sorted, so that the order messages go out in doesn't depend on hashing
+    pub fn clients_with_loaded(&self, lid: LocationID) -> Vec<ClientID> {
+        let mut clients: Vec<ClientID> = self.sessions.iter()
+            .filter(|&(_, s)| s.loaded.contains(&lid))
+            .map(|(c, _)| *c)
+            .collect();
+        clients.sort();
+        clients
+    }
+
+    // tells a client that just loaded `lid` about everyone else already there
+    fn introduce_occupants(&self, client: ClientID, lid: LocationID) -> Outgoing {
+        let avatar = self.avatar_of(client);
+        self.entities.in_location(lid).into_iter()
+        .filter(|e| Some(*e) != avatar)
+        .filter_map(|e| self.entities.spawn_diff_for(e))
+        .map(|d| (client, ServerMessage::ApplyDiff(lid, ServerDiff::Entity(d))))
+        .collect()
+    }
+
+    // the location the diff's subject is in right now
+    fn entity_lid(&self, diff: &EntityDiff) -> Option<LocationID> {
+        let eid = match diff {
+            &EntityDiff::Spawn(_, _, at) => return Some(at.lid),
+            &EntityDiff::Move(eid, _) => eid,
+            &EntityDiff::Despawn(eid) => eid,
+            &EntityDiff::SetHealth(eid, _) => eid,
+            &EntityDiff::PickUp(holder, _) => holder,
+            &EntityDiff::Drop(holder, _) => holder,
+        };
+        self.entities.position_of(eid).map(|p| p.lid)
+    }
+
+    // applies a diff (eg: from some server-side system) and tells everyone who can see it.
+    // moves between locations are announced to both the old and the new location
+    pub fn apply_entity_diff(&mut self, diff: EntityDiff) -> Outgoing {
+        let before = self.entity_lid(&diff);
+        self.entities.apply(&diff);
+        let after = self.entity_lid(&diff);
+        let mut out = vec![];
+        if let Some(lid) = before {
+            out.extend(self.broadcast_diff(lid, ServerDiff::Entity(diff)));
+        }
+        // those who only see the new location have never heard of the entity
+        if let (Some(lid), &EntityDiff::Move(eid, _)) = (after, &diff) {
+            if before != after {
+                let spawn = self.entities.spawn_diff_for(eid).unwrap();
+                out.extend(
+                    self.clients_with_loaded(lid).into_iter()
+                    .filter(|c| before.map(|b| !self.is_loaded_by(*c, b)).unwrap_or(true))
+                    .map(|c| (c, ServerMessage::ApplyDiff(lid, ServerDiff::Entity(spawn))))
+                );
+            }
+        }
+        out
+    }
+
+    pub fn broadcast_diff(&self, lid: LocationID, diff: ServerDiff) -> Outgoing {
+        self.clients_with_loaded(lid).into_iter()
+        .map(|c| (c, ServerMessage::ApplyDiff(lid, diff.clone())))
+        .collect()
+    }
+
+    pub fn handle(&mut self, client: ClientID, msg: ClientMessage) -> Result<Outgoing, ServerError> {
+        if !self.sessions.contains_key(&client) {
+            return Err(ServerError::UnknownClient(client));
+        }
+        match msg {
+            ClientMessage::TraversePortal(entry_point) => self.traverse_portal(client, entry_point),
+            ClientMessage::EditLocation(lid, diff) => {
+                if !self.is_loaded_by(client, lid) {
+                    return Err(ServerError::NotLoaded(client, lid));
+                }
+                // remembered, so that clients loading it later see the edit too
+                match self.sources.get_mut(&lid) {
+                    Some(&mut LocationSource::Zone(ref mut d)) => d.push(diff),
+                    Some(_) => return Err(ServerError::WrongLocationKind(lid)),
+                    None => return Err(ServerError::UnknownLocation(lid)),
+                }
+                Ok(self.broadcast_diff(lid, ServerDiff::Location(diff)))
+            },
+            ClientMessage::UnloadLID(lid) => {
+                // can't unload where you are standing
+                if self.position_of(client).map(|p| p.lid) != Some(lid) {
+                    self.sessions.get_mut(&client).unwrap().loaded.remove(&lid);
+                }
+                Ok(vec![])
+            },
+        }
+    }
+
+    fn traverse_portal(&mut self, client: ClientID, entry_point: UniquePoint) -> Result<Outgoing, ServerError> {
+        let avatar = self.sessions[&client].avatar;
+        let from = self.entities.position_of(avatar).expect("avatars always have a position");
+        if from.lid != entry_point.get_lid() {
+            return Err(ServerError::NotInLocation(client, entry_point.get_lid()));
+        }
+        let portal = self.resolver.resolve(&entry_point).ok_or(ServerError::NoSuchPortal(entry_point))?;
+        let arrival = match portal {
+            Portal::Nonspecific(lid) => {
+                let pt = self.spawn_points.get(&lid).ok_or(ServerError::NoArrivalPoint(lid))?;
+                UniquePoint::new(lid, *pt)
+            },
+            _ => portal.arrival_point().unwrap(),
+        };
+        if !self.sources.contains_key(&arrival.get_lid()) {
+            return Err(ServerError::UnknownLocation(arrival.get_lid()));
+        }
+
+        let mut out = vec![];
+        if self.sessions.get_mut(&client).unwrap().loaded.insert(arrival.get_lid()) {
+            out.push((client, self.load_message(arrival.get_lid())?));
+            out.extend(self.introduce_occupants(client, arrival.get_lid()));
+        }
+        let to = EntityPosition::new(arrival.get_lid(), arrival.get_c_pt().floor());
+        out.extend(self.apply_entity_diff(EntityDiff::Move(avatar, to)));
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DPoint2;
+    use super::super::primitive::Diffed;
+    use super::super::world::{World,WorldPrimitive};
+    use super::super::world::interior::{InteriorPrimitive,Race};
+    use super::super::world::placement::ObjectKind;
+
+    fn up(lid: LocationID, x: f32, y: f32) -> UniquePoint {
+        UniquePoint::new(lid, CPoint2::new(x, y))
+    }
+
+    fn at(lid: LocationID, x: i32, y: i32) -> EntityPosition {
+        EntityPosition::new(lid, DPoint2::new(x, y))
+    }
+
+    // 1 is a zone, 2 and 3 are interiors. (3,3) in 1 leads to (7,7) in 2 and back
+    fn server() -> Server {
+        let mut s = Server::new(PortalResolver::new());
+        let w = World::new(WorldPrimitive::new(0, 0.5, 0.5));
+        s.add_location(1, LocationSource::Zone(Diffed::new(w.location_primitive(0, 7).unwrap())));
+        for lid in 2..4 {
+            let door = up(1, 3.0, 3.0);
+            s.add_location(lid, LocationSource::Interior(Diffed::new(InteriorPrimitive::new(lid, 0.5, Race::Human, 0.1, door))));
+        }
+        s.get_resolver_mut().define_bidirectional(up(1, 3.0, 3.0), Portal::Discrete(DPoint2::new(7, 7), 2)).unwrap();
+        s
+    }
+
+    fn loads(out: &Outgoing) -> Vec<(ClientID, LocationID)> {
+        out.iter().filter_map(|&(c, ref m)| match m {
+            &ServerMessage::LoadLID(lid, _) => Some((c, lid)),
+            _ => None,
+        }).collect()
+    }
+
+    fn diffs(out: &Outgoing) -> Vec<(ClientID, LocationID, ServerDiff)> {
+        out.iter().filter_map(|&(c, ref m)| match m {
+            &ServerMessage::ApplyDiff(lid, ref d) => Some((c, lid, d.clone())),
+            _ => None,
+        }).collect()
+    }
+
+    fn spawn(eid: EntityID, kind: EntityKind, pos: EntityPosition) -> ServerDiff {
+        ServerDiff::Entity(EntityDiff::Spawn(eid, kind, pos))
+    }
+
+    #[test]
+    fn connecting_and_disconnecting() {
+        let mut s = server();
+        let (a, out) = s.connect(up(2, 1.0, 1.0)).unwrap();
+        let ea = s.avatar_of(a).unwrap();
+        assert_eq!(loads(&out), vec![(a, 2)]);
+        assert!(out.iter().any(|&(c, ref m)| match m {
+            &ServerMessage::YouAre(e) => c == a && e == ea,
+            _ => false,
+        }));
+        assert_eq!(diffs(&out), vec![(a, 2, spawn(ea, EntityKind::Player, at(2, 1, 1)))]);
+
+        // b hears about a, then everyone hears about b
+        let (b, out) = s.connect(up(2, 2.0, 1.0)).unwrap();
+        let eb = s.avatar_of(b).unwrap();
+        assert_eq!(diffs(&out), vec![
+            (b, 2, spawn(ea, EntityKind::Player, at(2, 1, 1))),
+            (a, 2, spawn(eb, EntityKind::Player, at(2, 2, 1))),
+            (b, 2, spawn(eb, EntityKind::Player, at(2, 2, 1))),
+        ]);
+
+        let out = s.disconnect(a).unwrap();
+        assert_eq!(diffs(&out), vec![(b, 2, ServerDiff::Entity(EntityDiff::Despawn(ea)))]);
+        assert_eq!(s.avatar_of(a), None);
+        assert_eq!(s.get_entities().position_of(ea), None);
+        assert_eq!(s.clients_with_loaded(2), vec![b]);
+        match s.disconnect(a) {
+            Err(ServerError::UnknownClient(c)) if c == a => (),
+            other => panic!("expected an unknown client, got {:?}", other),
+        }
+        match s.connect(up(9, 0.0, 0.0)) {
+            Err(ServerError::UnknownLocation(9)) => (),
+            other => panic!("expected an unknown location, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn portals_move_the_session() {
+        let mut s = server();
+        let (a, _) = s.connect(up(1, 3.0, 3.0)).unwrap();
+        let (b, _) = s.connect(up(2, 0.0, 0.0)).unwrap();
+        let (ea, eb) = (s.avatar_of(a).unwrap(), s.avatar_of(b).unwrap());
+
+        let out = s.handle(a, ClientMessage::TraversePortal(up(1, 3.0, 3.0))).unwrap();
+        assert_eq!(loads(&out), vec![(a, 2)]);
+        assert_eq!(diffs(&out), vec![
+            (a, 2, spawn(eb, EntityKind::Player, at(2, 0, 0))),
+            (a, 1, ServerDiff::Entity(EntityDiff::Move(ea, at(2, 7, 7)))),
+            (b, 2, spawn(ea, EntityKind::Player, at(2, 7, 7))),
+        ]);
+        assert_eq!(s.position_of(a), Some(at(2, 7, 7)));
+        assert!(s.is_loaded_by(a, 1) && s.is_loaded_by(a, 2));
+
+        // the way back needs nothing loaded
+        match s.handle(a, ClientMessage::TraversePortal(up(1, 3.0, 3.0))) {
+            Err(ServerError::NotInLocation(c, 1)) if c == a => (),
+            other => panic!("expected not in location, got {:?}", other),
+        }
+        let out = s.handle(a, ClientMessage::TraversePortal(up(2, 7.0, 7.0))).unwrap();
+        assert_eq!(loads(&out), vec![]);
+        assert_eq!(s.position_of(a), Some(at(1, 3, 3)));
+
+        match s.handle(a, ClientMessage::TraversePortal(up(1, 0.5, 0.5))) {
+            Err(ServerError::NoSuchPortal(p)) if p == up(1, 0.5, 0.5) => (),
+            other => panic!("expected no such portal, got {:?}", other),
+        }
+        match s.handle(99, ClientMessage::UnloadLID(1)) {
+            Err(ServerError::UnknownClient(99)) => (),
+            other => panic!("expected an unknown client, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn edits_only_reach_those_with_the_location_loaded() {
+        let mut s = server();
+        let (a, _) = s.connect(up(1, 0.0, 0.0)).unwrap();
+        let (b, _) = s.connect(up(2, 0.0, 0.0)).unwrap();
+        let edit = LocationDiff::PlaceObject(DPoint2::new(3, 4), ObjectKind::Ruin);
+
+        let out = s.handle(a, ClientMessage::EditLocation(1, edit)).unwrap();
+        assert_eq!(diffs(&out), vec![(a, 1, ServerDiff::Location(edit))]);
+        match s.get_source(1) {
+            Some(&LocationSource::Zone(ref d)) => assert_eq!(d.get_diffs(), &[edit]),
+            other => panic!("expected a zone, got {:?}", other),
+        }
+        match s.handle(b, ClientMessage::EditLocation(1, edit)) {
+            Err(ServerError::NotLoaded(c, 1)) if c == b => (),
+            other => panic!("expected not loaded, got {:?}", other),
+        }
+        match s.handle(b, ClientMessage::EditLocation(2, edit)) {
+            Err(ServerError::WrongLocationKind(2)) => (),
+            other => panic!("expected the wrong location kind, got {:?}", other),
+        }
+
+        // once b loads it, b hears about edits too
+        s.handle(b, ClientMessage::TraversePortal(up(2, 7.0, 7.0))).unwrap();
+        let out = s.handle(a, ClientMessage::EditLocation(1, edit)).unwrap();
+        assert_eq!(diffs(&out), vec![
+            (a, 1, ServerDiff::Location(edit)),
+            (b, 1, ServerDiff::Location(edit)),
+        ]);
+    }
+
+    #[test]
+    fn moves_are_announced_to_both_locations() {
+        let mut s = server();
+        let (a, _) = s.connect(up(1, 0.0, 0.0)).unwrap();
+        let (b, _) = s.connect(up(2, 0.0, 0.0)).unwrap();
+        let (c, _) = s.connect(up(3, 0.0, 0.0)).unwrap();
+        // d can see both
+        let (d, _) = s.connect(up(1, 3.0, 3.0)).unwrap();
+        s.handle(d, ClientMessage::TraversePortal(up(1, 3.0, 3.0))).unwrap();
+        let npc = 1000;
+
+        let out = s.apply_entity_diff(EntityDiff::Spawn(npc, EntityKind::Npc, at(1, 5, 5)));
+        assert_eq!(diffs(&out), vec![
+            (a, 1, spawn(npc, EntityKind::Npc, at(1, 5, 5))),
+            (d, 1, spawn(npc, EntityKind::Npc, at(1, 5, 5))),
+        ]);
+
+        let out = s.apply_entity_diff(EntityDiff::Move(npc, at(2, 1, 1)));
+        let moved = ServerDiff::Entity(EntityDiff::Move(npc, at(2, 1, 1)));
+        assert_eq!(diffs(&out), vec![
+            (a, 1, moved.clone()),
+            (d, 1, moved),
+            (b, 2, spawn(npc, EntityKind::Npc, at(2, 1, 1))),
+        ]);
+        assert!(out.iter().all(|&(to, _)| to != c));
+        assert_eq!(s.get_entities().position_of(npc), Some(at(2, 1, 1)));
+    }
+
+    #[test]
+    fn unloading_locations() {
+        let mut s = server();
+        let (a, _) = s.connect(up(1, 3.0, 3.0)).unwrap();
+        s.handle(a, ClientMessage::TraversePortal(up(1, 3.0, 3.0))).unwrap();
+
+        // can't unload where you are standing
+        assert_eq!(s.handle(a, ClientMessage::UnloadLID(2)).unwrap().len(), 0);
+        assert!(s.is_loaded_by(a, 2));
+
+        s.handle(a, ClientMessage::UnloadLID(1)).unwrap();
+        assert!(!s.is_loaded_by(a, 1));
+        assert!(s.clients_with_loaded(1).is_empty());
+        let edit = LocationDiff::RemoveObject(DPoint2::new(0, 0));
+        match s.handle(a, ClientMessage::EditLocation(1, edit)) {
+            Err(ServerError::NotLoaded(c, 1)) if c == a => (),
+            other => panic!("expected not loaded, got {:?}", other),
+        }
+
+        // going back loads it again
+        let out = s.handle(a, ClientMessage::TraversePortal(up(2, 7.0, 7.0))).unwrap();
+        assert_eq!(loads(&out), vec![(a, 1)]);
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::{HashMap,BTreeSet};
+use std::mem::size_of;
+use ::points::DPoint2;
+use super::grid::{TotalGrid,Neighbourhood};
+
+/*
+A grid too big (or endless) to hold at once. it is cut into square chunks of
+`chunk_side` cells, each made by calling the generator for every cell the first
+time anything in it is asked for.
+
+Only as many chunks as fit the memory budget are kept. making room throws out
+the chunk used longest ago; if it was written to, the edited cells are kept
+aside and written back over the regenerated chunk when it's next needed. so
+the generator must give the same element for the same cell every time.
+
+the budget counts `size_of::<T>()` per cell of the resident chunks, and per
+edit kept aside (plus its index), so the more edits pile up the fewer chunks
+stay resident. it doesn't count what elements own on the heap. edits are
+never thrown away, so once they alone fill the budget only one chunk is kept.
+
+cells are points like TotalGrid's `at` and `in_bounds` take, as they may be
+negative. `get`, `put`, `maybe_get` and `maybe_get_mut` take (x, y) like
+TotalGrid's, so only reach the cells from (0,0) on. reading may generate a
+chunk, so needs `&mut`, except `peek` which only sees resident chunks. there is
+no whole-grid `map` or iterator, as the grid may be endless; copy a rectangle
+out with `to_grid` for those.
+*/
+
+struct Chunk<T> {
+    elements: TotalGrid<T>,
+    // local indices (row-major) of cells written to since it was generated
+    edited: BTreeSet<usize>,
+    last_used: u64,
+}
+
+pub struct ChunkedGrid<T> {
+    chunk_side: i32,
+    // None: no edges at all, in any direction
+    dimensions: Option<DPoint2>,
+    generator: Box<FnMut(DPoint2) -> T>,
+    budget_bytes: usize,
+    chunk_bytes: usize,
+    chunks: HashMap<DPoint2, Chunk<T>>,
+    // edits of evicted chunks, by chunk
+    evicted_edits: HashMap<DPoint2, Vec<(usize, T)>>,
+    evicted_edit_count: usize,
+    // counts up on every use. the chunk with the lowest last_used goes first
+    clock: u64,
+    generated: u64,
+}
+
+// rounding towards negative infinity, so cells at -1 are in chunk -1
+fn div_floor(a: i32, b: i32) -> i32 {
+    if a >= 0 {a / b} else {(a - b + 1) / b}
+}
+
+impl<T> ChunkedGrid<T> {
+    // at least one chunk is always kept, however small the budget
+    pub fn new(chunk_side: i32, budget_bytes: usize, generator: Box<FnMut(DPoint2) -> T>) -> ChunkedGrid<T> {
+        assert!(chunk_side > 0);
+        ChunkedGrid {
+            chunk_side: chunk_side,
+            dimensions: None,
+            generator: generator,
+            budget_bytes: budget_bytes,
+            chunk_bytes: ::std::cmp::max(size_of::<T>() * (chunk_side * chunk_side) as usize, 1),
+            chunks: HashMap::new(),
+            evicted_edits: HashMap::new(),
+            evicted_edit_count: 0,
+            clock: 0,
+            generated: 0,
+        }
+    }
+
+    // only cells from (0,0) up to `dimensions` exist
+    pub fn with_dimensions(mut self, dimensions: DPoint2) -> ChunkedGrid<T> {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    pub fn get_dimensions(&self) -> Option<DPoint2> {self.dimensions}
+    pub fn get_chunk_side(&self) -> i32 {self.chunk_side}
+    pub fn get_budget_bytes(&self) -> usize {self.budget_bytes}
+    // what is left of the budget after the edits kept aside, in whole chunks
+    pub fn get_max_chunks(&self) -> usize {
+        ::std::cmp::max(self.budget_bytes.saturating_sub(self.evicted_edit_bytes()) / self.chunk_bytes, 1)
+    }
+    pub fn resident_chunks(&self) -> usize {self.chunks.len()}
+    pub fn resident_bytes(&self) -> usize {
+        self.chunks.values().map(|c| size_of::<T>() * c.elements.get_dimensions().x as usize * c.elements.get_dimensions().y as usize).sum()
+    }
+    pub fn evicted_edit_bytes(&self) -> usize {
+        self.evicted_edit_count * size_of::<(usize, T)>()
+    }
+    // how many times a chunk has been generated, the first time or again after eviction
+    pub fn chunks_generated(&self) -> u64 {self.generated}
+
+    pub fn in_bounds(&self, pt: DPoint2) -> bool {
+        match self.dimensions {
+            Some(dims) => pt.x >= 0 && pt.y >= 0 && pt.x < dims.x && pt.y < dims.y,
+            None => true,
+        }
+    }
+
+    // the chunk `pt` is in
+    pub fn chunk_of(&self, pt: DPoint2) -> DPoint2 {
+        DPoint2::new(div_floor(pt.x, self.chunk_side), div_floor(pt.y, self.chunk_side))
+    }
+
+    pub fn is_resident(&self, chunk: DPoint2) -> bool {self.chunks.contains_key(&chunk)}
+
+    // the chunk's top left cell, and where `pt` is inside it
+    fn split(&self, pt: DPoint2) -> (DPoint2, DPoint2) {
+        let chunk = self.chunk_of(pt);
+        (chunk, DPoint2::new(pt.x - chunk.x * self.chunk_side, pt.y - chunk.y * self.chunk_side))
+    }
+
+    // makes sure the chunk is resident, and marks it as just used
+    fn load(&mut self, chunk: DPoint2) -> &mut Chunk<T> {
+        self.clock += 1;
+        let now = self.clock;
+        if !self.chunks.contains_key(&chunk) {
+            // evicting an edited chunk makes the budget tighter still
+            while !self.chunks.is_empty() && self.chunks.len() >= self.get_max_chunks() {
+                self.evict_oldest();
+            }
+            let generated = self.generate(chunk);
+            self.chunks.insert(chunk, generated);
+        }
+        let c = self.chunks.get_mut(&chunk).unwrap();
+        c.last_used = now;
+        c
+    }
+
+    fn generate(&mut self, chunk: DPoint2) -> Chunk<T> {
+        let tl = DPoint2::new(chunk.x * self.chunk_side, chunk.y * self.chunk_side);
+        let dims = self.chunk_dimensions(chunk);
+        let generator = &mut self.generator;
+        let mut elements = TotalGrid::new_from_func(dims, &mut |x, y| {
+            generator(DPoint2::new(tl.x + x as i32, tl.y + y as i32))
+        });
+        let mut edited = BTreeSet::new();
+        if let Some(edits) = self.evicted_edits.remove(&chunk) {
+            self.evicted_edit_count -= edits.len();
+            let width = dims.x as usize;
+            for (i, e) in edits {
+                elements.put(i % width, i / width, e);
+                edited.insert(i);
+            }
+        }
+        self.generated += 1;
+        Chunk {elements: elements, edited: edited, last_used: 0}
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = match self.chunks.iter().min_by_key(|&(_, c)| c.last_used) {
+            Some((k, _)) => *k,
+            None => return,
+        };
+        let chunk = self.chunks.remove(&oldest).unwrap();
+        if !chunk.edited.is_empty() {
+            let edited = chunk.edited;
+            // moved out rather than cloned, as the chunk is going anyway
+            let edits: Vec<(usize, T)> = chunk.elements.into_elements().into_iter()
+                .enumerate()
+                .filter(|&(i, _)| edited.contains(&i))
+                .collect();
+            self.evicted_edit_count += edits.len();
+            self.evicted_edits.insert(oldest, edits);
+        }
+    }
+
+    // None outside the grid. generates the chunk if needed
+    pub fn at(&mut self, pt: DPoint2) -> Option<&T> {
+        if !self.in_bounds(pt) {return None}
+        let (chunk, local) = self.split(pt);
+        self.load(chunk).elements.at(local)
+    }
+
+    // None outside the grid, and for cells of chunks that aren't resident
+    pub fn peek(&self, pt: DPoint2) -> Option<&T> {
+        if !self.in_bounds(pt) {return None}
+        let (chunk, local) = self.split(pt);
+        self.chunks.get(&chunk).and_then(|c| c.elements.at(local))
+    }
+
+    // TotalGrid-style coordinates as a cell. None if that's outside the grid
+    fn cell(&self, x: usize, y: usize) -> Option<DPoint2> {
+        let max = ::std::i32::MAX as usize;
+        if x > max || y > max {return None}
+        let pt = DPoint2::new(x as i32, y as i32);
+        if self.in_bounds(pt) {Some(pt)} else {None}
+    }
+
+    // for writing. the cell is kept as an edit from now on. `pt` must be in the grid
+    fn edit(&mut self, pt: DPoint2) -> &mut T {
+        let (chunk, local) = self.split(pt);
+        let c = self.load(chunk);
+        let i = (local.y * c.elements.get_width() + local.x) as usize;
+        c.edited.insert(i);
+        &mut c.elements[local]
+    }
+
+    // there is no at_mut: every write goes through here, `put_at`, `put` or
+    // `maybe_get_mut`, so only cells written to are kept as edits. false outside the grid
+    pub fn update<F>(&mut self, pt: DPoint2, f: F) -> bool
+    where F: FnOnce(&mut T) {
+        if !self.in_bounds(pt) {return false}
+        f(self.edit(pt));
+        true
+    }
+
+    // panics outside the grid, like TotalGrid::put
+    pub fn put_at(&mut self, pt: DPoint2, element: T) {
+        if !self.update(pt, |e| *e = element) {
+            panic!("{:?} is outside a chunked grid of {:?}", pt, self.dimensions);
+        }
+    }
+
+    // panics outside the grid, like TotalGrid::get
+    pub fn get(&mut self, x: usize, y: usize) -> &T {
+        match self.cell(x, y) {
+            Some(pt) => self.at(pt).unwrap(),
+            None => panic!("({}, {}) is outside a chunked grid of {:?}", x, y, self.dimensions),
+        }
+    }
+
+    pub fn maybe_get(&mut self, x: usize, y: usize) -> Option<&T> {
+        match self.cell(x, y) {
+            Some(pt) => self.at(pt),
+            None => None,
+        }
+    }
+
+    // counts as a write, so the cell is kept as an edit even if nothing is changed through it
+    pub fn maybe_get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        match self.cell(x, y) {
+            Some(pt) => Some(self.edit(pt)),
+            None => None,
+        }
+    }
+
+    // panics outside the grid, like TotalGrid::put
+    pub fn put(&mut self, x: usize, y: usize, element: T) {
+        match self.cell(x, y) {
+            Some(pt) => *self.edit(pt) = element,
+            None => panic!("({}, {}) is outside a chunked grid of {:?}", x, y, self.dimensions),
+        }
+    }
+
+    // the neighbouring cells that lie inside the grid
+    pub fn neighbours(&self, pt: DPoint2, hood: Neighbourhood) -> Vec<DPoint2> {
+        hood.offsets().iter()
+        .map(|&(dx, dy)| DPoint2::new(pt.x + dx, pt.y + dy))
+        .filter(|n| self.in_bounds(*n))
+        .collect()
+    }
+
+    pub fn neighbours4(&self, pt: DPoint2) -> Vec<DPoint2> {self.neighbours(pt, Neighbourhood::Four)}
+    pub fn neighbours8(&self, pt: DPoint2) -> Vec<DPoint2> {self.neighbours(pt, Neighbourhood::Eight)}
+
+    // every edited cell, resident or not. sorted by row, then column
+    pub fn edited_cells(&self) -> Vec<DPoint2> {
+        let side = self.chunk_side;
+        let cell = |chunk: DPoint2, width: i32, i: usize| {
+            DPoint2::new(chunk.x * side + i as i32 % width, chunk.y * side + i as i32 / width)
+        };
+        let mut cells: Vec<DPoint2> = self.chunks.iter()
+            .flat_map(|(k, c)| c.edited.iter().map(move |i| cell(*k, c.elements.get_width(), *i)))
+            .collect();
+        for (k, edits) in self.evicted_edits.iter() {
+            let width = self.chunk_dimensions(*k).x;
+            cells.extend(edits.iter().map(|&(i, _)| cell(*k, width, i)));
+        }
+        cells.sort_by_key(|pt| (pt.y, pt.x));
+        cells
+    }
+
+    // the size of a chunk, cut short on the far edges of a bounded grid
+    fn chunk_dimensions(&self, chunk: DPoint2) -> DPoint2 {
+        let tl = DPoint2::new(chunk.x * self.chunk_side, chunk.y * self.chunk_side);
+        match self.dimensions {
+            Some(d) => DPoint2::new(
+                ::std::cmp::min(self.chunk_side, d.x - tl.x),
+                ::std::cmp::min(self.chunk_side, d.y - tl.y),
+            ),
+            None => DPoint2::new(self.chunk_side, self.chunk_side),
+        }
+    }
+}
+
+impl<T: Clone> ChunkedGrid<T> {
+    // a copy of the `dims`-sized rectangle with its top left at `tl`, eg: to find paths in.
+    // None unless it lies entirely inside the grid
+    pub fn to_grid(&mut self, tl: DPoint2, dims: DPoint2) -> Option<TotalGrid<T>> {
+        if dims.x <= 0 || dims.y <= 0
+        || !self.in_bounds(tl)
+        || !self.in_bounds(DPoint2::new(tl.x + dims.x - 1, tl.y + dims.y - 1)) {
+            return None
+        }
+        Some(TotalGrid::new_from_func(dims, &mut |x, y| {
+            self.at(DPoint2::new(tl.x + x as i32, tl.y + y as i32)).unwrap().clone()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2 chunks of 8 byte cells: 32 bytes a chunk, and 16 for each edit kept aside
+    fn numbered(chunks: usize) -> ChunkedGrid<i64> {
+        ChunkedGrid::new(2, chunks * 32, Box::new(|pt: DPoint2| (pt.y * 100 + pt.x) as i64))
+    }
+
+    #[test]
+    fn only_writes_are_edits() {
+        let mut g = numbered(1);
+        for x in 0..6 {
+            assert_eq!(g.at(DPoint2::new(x, 1)), Some(&(100 + x as i64)));
+        }
+        assert!(g.edited_cells().is_empty());
+
+        g.put_at(DPoint2::new(1, 1), 7);
+        assert!(g.update(DPoint2::new(-1, 0), |e| *e += 1));
+        assert_eq!(g.edited_cells(), vec![DPoint2::new(-1, 0), DPoint2::new(1, 1)]);
+        // both evicted by now, and brought back when read
+        assert!(!g.is_resident(g.chunk_of(DPoint2::new(1, 1))));
+        assert_eq!(g.at(DPoint2::new(1, 1)), Some(&7));
+        assert_eq!(g.at(DPoint2::new(-1, 0)), Some(&0));
+        assert_eq!(g.at(DPoint2::new(0, 1)), Some(&100));
+    }
+
+    #[test]
+    fn evicted_edits_use_up_the_budget() {
+        let mut g = numbered(4);
+        assert_eq!(g.get_max_chunks(), 4);
+        for y in 0..2 {
+            for x in 0..2 {
+                g.put_at(DPoint2::new(x, y), 0);
+            }
+        }
+        // pushes the edited chunk out
+        for x in 1..5 {
+            g.at(DPoint2::new(x * 2, 0));
+        }
+        assert!(!g.is_resident(DPoint2::new(0, 0)));
+        assert_eq!(g.evicted_edit_bytes(), 64);
+        assert_eq!(g.get_max_chunks(), 2);
+        assert!(g.resident_bytes() + g.evicted_edit_bytes() <= g.get_budget_bytes());
+        // and they are back in the chunk once it is
+        assert_eq!(g.at(DPoint2::new(1, 1)), Some(&0));
+        assert_eq!(g.evicted_edit_bytes(), 0);
+        assert_eq!(g.get_max_chunks(), 4);
+    }
+
+    #[test]
+    fn peek_sees_only_resident_chunks() {
+        let mut g = numbered(1).with_dimensions(DPoint2::new(4, 4));
+        assert_eq!(g.peek(DPoint2::new(0, 0)), None);
+        g.at(DPoint2::new(0, 0));
+        assert_eq!(g.peek(DPoint2::new(1, 1)), Some(&101));
+        assert_eq!(g.peek(DPoint2::new(2, 0)), None);
+        assert_eq!(g.peek(DPoint2::new(-1, 0)), None);
+        assert!(!g.update(DPoint2::new(4, 0), |e| *e = 0));
+    }
+
+    // the same cells as a TotalGrid, for comparing against
+    fn both(dims: DPoint2) -> (ChunkedGrid<i64>, TotalGrid<i64>) {
+        let total = TotalGrid::new_from_func(dims, &mut |x, y| (y * 100 + x) as i64);
+        (numbered(2).with_dimensions(dims), total)
+    }
+
+    #[test]
+    fn reads_like_a_total_grid() {
+        let (mut g, total) = both(DPoint2::new(7, 5));
+        for y in 0..7 {
+            for x in 0..9 {
+                assert_eq!(g.maybe_get(x, y), total.maybe_get(x, y), "({}, {})", x, y);
+                if x < 7 && y < 5 {
+                    assert_eq!(g.get(x, y), total.get(x, y));
+                }
+            }
+        }
+        assert_eq!(g.maybe_get(::std::usize::MAX, 0), None);
+        assert!(g.edited_cells().is_empty());
+        // endless grids have every cell from (0,0) on
+        assert_eq!(numbered(1).get(1000, 3), &1300);
+    }
+
+    #[test]
+    fn writes_like_a_total_grid() {
+        let dims = DPoint2::new(7, 5);
+        let (mut g, mut total) = both(dims);
+        for &(x, y) in [(0, 0), (6, 4), (3, 2), (4, 0)].iter() {
+            g.put(x, y, -1);
+            total.put(x, y, -1);
+        }
+        for &(x, y) in [(1, 1), (6, 0), (3, 2)].iter() {
+            *g.maybe_get_mut(x, y).unwrap() += 10;
+            *total.maybe_get_mut(x, y).unwrap() += 10;
+        }
+        assert_eq!(g.maybe_get_mut(7, 0), None);
+        assert_eq!(total.maybe_get_mut(7, 0), None);
+        assert_eq!(g.maybe_get_mut(0, 5), None);
+        // through eviction and back
+        assert_eq!(g.to_grid(DPoint2::new(0, 0), dims), Some(total));
+        assert_eq!(g.edited_cells().len(), 6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn get_past_the_right_edge_panics() {
+        both(DPoint2::new(7, 5)).0.get(7, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn put_past_the_bottom_edge_panics() {
+        both(DPoint2::new(7, 5)).0.put(0, 5, 1);
+    }
+}
@@ -1,8 +1,91 @@
+use std::ops::{Index,IndexMut};
 use ::points::DPoint2;
 
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
 pub struct TotalGrid<T> {
     elements: Vec<T>,
     width: i32,
+    #[serde(default)]
+    topology: Topology,
+}
+
+// which cells lie next to which across the grid's edges
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,Serialize,Deserialize)]
+pub enum Topology {
+    // nothing beyond the edges
+    Bounded,
+    // the left and right edges meet
+    WrapX,
+    // the left and right edges meet, and so do the top and bottom
+    Torus,
+    // like a World: the left and right edges meet, and going over the top (or
+    // bottom) edge comes back down on the far side of the pole, half way around.
+    // needs an even width. with an odd one, the poles are bounded instead
+    Sphere,
+}
+
+impl Default for Topology {
+    fn default() -> Topology {Topology::Bounded}
+}
+
+// x in 0..m, for any x
+fn wrap_around(x: i32, m: i32) -> i32 {
+    ((x % m) + m) % m
+}
+
+impl Topology {
+    // the cell `pt` stands for on a grid of `dims`. None if it's off the grid
+    pub fn wrap(self, dims: DPoint2, pt: DPoint2) -> Option<DPoint2> {
+        if dims.x <= 0 || dims.y <= 0 {return None}
+        let (mut x, mut y) = (pt.x, pt.y);
+        match self {
+            Topology::Bounded => (),
+            Topology::WrapX => x = wrap_around(x, dims.x),
+            Topology::Torus => {
+                x = wrap_around(x, dims.x);
+                y = wrap_around(y, dims.y);
+            },
+            Topology::Sphere => {
+                if dims.x % 2 == 0 {
+                    // each trip over a pole turns you half way around
+                    y = wrap_around(y, 2 * dims.y);
+                    if y >= dims.y {
+                        y = 2 * dims.y - 1 - y;
+                        x += dims.x / 2;
+                    }
+                }
+                x = wrap_around(x, dims.x);
+            },
+        }
+        if x >= 0 && y >= 0 && x < dims.x && y < dims.y {
+            Some(DPoint2::new(x, y))
+        } else {
+            None
+        }
+    }
+
+    // how far apart (across, down) `a` and `b` are each way around there is,
+    // not counting steps that leave the grid. the shortest is one of these
+    pub fn separations(self, dims: DPoint2, a: DPoint2, b: DPoint2) -> Vec<(u32, u32)> {
+        let dx = (a.x - b.x).abs();
+        let dy = (a.y - b.y).abs();
+        let wrapped_dx = |dx: i32| ::std::cmp::min(dx, dims.x - dx) as u32;
+        match self {
+            Topology::Bounded => vec![(dx as u32, dy as u32)],
+            Topology::WrapX => vec![(wrapped_dx(dx), dy as u32)],
+            Topology::Torus => vec![(wrapped_dx(dx), ::std::cmp::min(dy, dims.y - dy) as u32)],
+            Topology::Sphere => {
+                let mut v = vec![(wrapped_dx(dx), dy as u32)];
+                if dims.x % 2 == 0 {
+                    let over_a_pole = wrapped_dx(wrap_around(a.x - b.x + dims.x / 2, dims.x));
+                    // over the top, and over the bottom
+                    v.push((over_a_pole, (a.y + b.y + 1) as u32));
+                    v.push((over_a_pole, (2 * dims.y - 1 - a.y - b.y) as u32));
+                }
+                v
+            },
+        }
+    }
 }
 
 fn cell_from_index(width: i32, index: i32) -> DPoint2 {
@@ -32,17 +115,32 @@ impl<'a, T> Iterator for TotalGridIterator<'a, T> {
     type Item = (DPoint2,&'a T);
 
     fn next(&mut self) -> Option<(DPoint2,&'a T)> {
-        let old = self.next_element;
-        self.next_element += 1;
-        if old == self.elements.len()-1 {
-            None
-        } else {
-            Some(
-                (
-                    cell_from_index(self.width, self.next_element as i32),
-                    self.elements.get(self.next_element).expect("FUARK"),
-                )
-            )
+        let i = self.next_element;
+        match self.elements.get(i) {
+            Some(element) => {
+                self.next_element += 1;
+                Some((cell_from_index(self.width, i as i32), element))
+            },
+            None => None,
+        }
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Neighbourhood {
+    // up, right, down, left
+    Four,
+    // the Four, then the diagonals
+    Eight,
+}
+
+const NEIGHBOUR_OFFSETS: [(i32, i32); 8] = [(0,-1), (1,0), (0,1), (-1,0), (1,-1), (1,1), (-1,1), (-1,-1)];
+
+impl Neighbourhood {
+    pub fn offsets(self) -> &'static [(i32, i32)] {
+        match self {
+            Neighbourhood::Four => &NEIGHBOUR_OFFSETS[..4],
+            Neighbourhood::Eight => &NEIGHBOUR_OFFSETS[..],
         }
     }
 }
@@ -78,27 +176,236 @@ impl<T> TotalGrid<T> {
         TotalGrid {
             elements: v,
             width: dimensions.x,
+            topology: Topology::Bounded,
         }
     }
 
+    pub fn with_topology(mut self, topology: Topology) -> TotalGrid<T> {
+        self.topology = topology;
+        self
+    }
+
+    // row-major, as they are stored
+    pub fn into_elements(self) -> Vec<T> {self.elements}
+
+    pub fn get_topology(&self) -> Topology {self.topology}
+    pub fn set_topology(&mut self, topology: Topology) {self.topology = topology}
+
+    // row-major: cell (x,y) lives at y*width + x. an x past the right edge would
+    // otherwise land on the next row, so it panics instead
+    fn index_of(&self, x: usize, y: usize) -> usize {
+        assert!(x < self.width as usize, "x = {} is outside a grid {} wide", x, self.width);
+        y * self.width as usize + x
+    }
+
     pub fn get_mut(&mut self, x: usize, y: usize) -> &mut T {
-        &mut self.elements[x*y]
+        let i = self.index_of(x, y);
+        &mut self.elements[i]
     }
 
     pub fn get(&self, x: usize, y: usize) -> &T {
-        &self.elements[x*y]
+        &self.elements[self.index_of(x, y)]
     }
 
     pub fn maybe_get(&self, x: usize, y: usize) -> Option<&T> {
-        self.elements.get(x*y)
+        if x >= self.width as usize {return None}
+        self.elements.get(self.index_of(x, y))
     }
 
     pub fn maybe_get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
-        self.elements.get_mut(x*y)
+        if x >= self.width as usize {return None}
+        let i = self.index_of(x, y);
+        self.elements.get_mut(i)
     }
 
     pub fn put(&mut self, x: usize, y: usize, element: T) {
-        self.elements[x*y] = element;
+        let i = self.index_of(x, y);
+        self.elements[i] = element;
+    }
+
+    pub fn in_bounds(&self, pt: DPoint2) -> bool {
+        pt.x >= 0 && pt.y >= 0 && pt.x < self.width && pt.y < self.get_height()
+    }
+
+    // the cell `pt` stands for under this grid's topology. None if it's off the grid
+    pub fn wrap(&self, pt: DPoint2) -> Option<DPoint2> {
+        self.topology.wrap(self.get_dimensions(), pt)
+    }
+
+    // None outside the grid. see `wrap` for points past a wrapping edge
+    pub fn at(&self, pt: DPoint2) -> Option<&T> {
+        if !self.in_bounds(pt) {return None}
+        Some(&self.elements[self.index_of(pt.x as usize, pt.y as usize)])
+    }
+
+    pub fn at_mut(&mut self, pt: DPoint2) -> Option<&mut T> {
+        if !self.in_bounds(pt) {return None}
+        let i = self.index_of(pt.x as usize, pt.y as usize);
+        Some(&mut self.elements[i])
+    }
+
+    // the neighbouring cells, across wrapping edges too. never `pt` itself,
+    // and each only once, even on grids small enough to wrap onto themselves
+    pub fn neighbours(&self, pt: DPoint2, hood: Neighbourhood) -> Vec<DPoint2> {
+        let mut v: Vec<DPoint2> = vec![];
+        for &(dx, dy) in hood.offsets().iter() {
+            if let Some(n) = self.wrap(DPoint2::new(pt.x + dx, pt.y + dy)) {
+                if n != pt && !v.contains(&n) {
+                    v.push(n);
+                }
+            }
+        }
+        v
+    }
+
+    pub fn neighbours4(&self, pt: DPoint2) -> Vec<DPoint2> {self.neighbours(pt, Neighbourhood::Four)}
+    pub fn neighbours8(&self, pt: DPoint2) -> Vec<DPoint2> {self.neighbours(pt, Neighbourhood::Eight)}
+
+    pub fn row(&self, y: i32) -> &[T] {
+        let start = self.index_of(0, y as usize);
+        &self.elements[start..start + self.width as usize]
+    }
+
+    // top to bottom
+    pub fn rows<'a>(&'a self) -> Box<Iterator<Item=&'a [T]> + 'a> {
+        Box::new(self.elements.chunks(self.width as usize))
+    }
+
+    // top to bottom. panics if x is off the grid
+    pub fn column<'a>(&'a self, x: i32) -> Box<Iterator<Item=&'a T> + 'a> {
+        assert!(x >= 0 && x < self.width, "x = {} is outside a grid {} wide", x, self.width);
+        let width = self.width as usize;
+        Box::new(self.elements.iter().skip(x as usize).enumerate().filter(move |&(i, _)| i % width == 0).map(|(_, e)| e))
+    }
+
+    // left to right
+    pub fn columns<'a>(&'a self) -> Box<Iterator<Item=Box<Iterator<Item=&'a T> + 'a>> + 'a> {
+        Box::new((0..self.width).map(move |x| self.column(x)))
+    }
+
+    // a new grid of the same size, from each cell and its element
+    pub fn map<U, F>(&self, mut f: F) -> TotalGrid<U>
+    where F: FnMut(DPoint2, &T) -> U {
+        TotalGrid {
+            elements: self.into_iter().map(|(pt, e)| f(pt, e)).collect(),
+            width: self.width,
+            topology: self.topology,
+        }
+    }
+
+    // None if the grids differ in size
+    pub fn zip_with<U, V, F>(&self, other: &TotalGrid<U>, mut f: F) -> Option<TotalGrid<V>>
+    where F: FnMut(DPoint2, &T, &U) -> V {
+        if self.get_dimensions() != other.get_dimensions() {return None}
+        Some(TotalGrid {
+            elements: self.into_iter().zip(other.elements.iter())
+                .map(|((pt, a), b)| f(pt, a, b))
+                .collect(),
+            width: self.width,
+            topology: self.topology,
+        })
+    }
+
+    // the `dims`-sized rectangle with its top left at `tl`. None unless it lies entirely inside the grid
+    pub fn view(&self, tl: DPoint2, dims: DPoint2) -> Option<GridView<T>> {
+        if dims.x <= 0 || dims.y <= 0
+        || !self.in_bounds(tl)
+        || !self.in_bounds(DPoint2::new(tl.x + dims.x - 1, tl.y + dims.y - 1)) {
+            return None
+        }
+        Some(GridView {grid: self, tl: tl, dims: dims})
+    }
+}
+
+impl<T: Clone> TotalGrid<T> {
+    pub fn new_filled(dimensions: DPoint2, element: T) -> TotalGrid<T> {
+        TotalGrid {
+            elements: vec![element; (dimensions.x * dimensions.y) as usize],
+            width: dimensions.x,
+            topology: Topology::Bounded,
+        }
+    }
+}
+
+// panics outside the grid. see `at` for the checked version
+impl<T> Index<DPoint2> for TotalGrid<T> {
+    type Output = T;
+    fn index(&self, pt: DPoint2) -> &T {
+        match self.at(pt) {
+            Some(e) => e,
+            None => panic!("{:?} is outside a grid of {:?}", pt, self.get_dimensions()),
+        }
+    }
+}
+
+impl<T> IndexMut<DPoint2> for TotalGrid<T> {
+    fn index_mut(&mut self, pt: DPoint2) -> &mut T {
+        let dims = self.get_dimensions();
+        match self.at_mut(pt) {
+            Some(e) => e,
+            None => panic!("{:?} is outside a grid of {:?}", pt, dims),
+        }
+    }
+}
+
+// a rectangle of some grid. points are relative to the rectangle's top left
+#[derive(Debug)]
+pub struct GridView<'a, T: 'a> {
+    grid: &'a TotalGrid<T>,
+    tl: DPoint2,
+    dims: DPoint2,
+}
+
+// by hand, as deriving would demand T: Copy
+impl<'a, T> Clone for GridView<'a, T> {
+    fn clone(&self) -> GridView<'a, T> {*self}
+}
+
+impl<'a, T> Copy for GridView<'a, T> {}
+
+impl<'a, T> GridView<'a, T> {
+    pub fn get_dimensions(&self) -> DPoint2 {self.dims}
+    pub fn get_top_left(&self) -> DPoint2 {self.tl}
+
+    pub fn in_bounds(&self, pt: DPoint2) -> bool {
+        pt.x >= 0 && pt.y >= 0 && pt.x < self.dims.x && pt.y < self.dims.y
+    }
+
+    pub fn at(&self, pt: DPoint2) -> Option<&'a T> {
+        if !self.in_bounds(pt) {return None}
+        self.grid.at(DPoint2::new(self.tl.x + pt.x, self.tl.y + pt.y))
+    }
+
+    pub fn row(&self, y: i32) -> &'a [T] {
+        let row = self.grid.row(self.tl.y + y);
+        &row[self.tl.x as usize..(self.tl.x + self.dims.x) as usize]
+    }
+
+    // row by row, with points relative to the view
+    pub fn iter(&self) -> Box<Iterator<Item=(DPoint2, &'a T)> + 'a> {
+        let view = *self;
+        Box::new(
+            (0..self.dims.y).flat_map(move |y| {
+                view.row(y).iter().enumerate().map(move |(x, e)| (DPoint2::new(x as i32, y), e))
+            })
+        )
+    }
+
+    // bounded, as a rectangle cut out of a grid doesn't wrap
+    pub fn map<U, F>(&self, mut f: F) -> TotalGrid<U>
+    where F: FnMut(DPoint2, &T) -> U {
+        TotalGrid {
+            elements: self.iter().map(|(pt, e)| f(pt, e)).collect(),
+            width: self.dims.x,
+            topology: Topology::Bounded,
+        }
+    }
+}
+
+impl<'a, T: Clone> GridView<'a, T> {
+    // a copy of just this rectangle
+    pub fn to_grid(&self) -> TotalGrid<T> {
+        self.map(|_, e| e.clone())
     }
 }
 
@@ -130,9 +437,243 @@ impl<T> TotalGridBuilder<T> {
             Ok(TotalGrid {
                 elements: self.elements,
                 width: dimensions.x,
+                topology: Topology::Bounded,
             })
         } else {
             Err(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use ::rand::{SeedableRng,Rng,Isaac64Rng};
+    use super::*;
+
+    const TOPOLOGIES: [Topology; 4] = [Topology::Bounded, Topology::WrapX, Topology::Torus, Topology::Sphere];
+
+    // wide, tall, single rows and columns, odd and even
+    fn shapes() -> Vec<DPoint2> {
+        let mut rng = Isaac64Rng::from_seed(&[47]);
+        let mut shapes: Vec<DPoint2> = (0..60).map(|_| DPoint2::new(rng.gen_range(1, 12), rng.gen_range(1, 12))).collect();
+        shapes.push(DPoint2::new(1, 1));
+        shapes
+    }
+
+    // anywhere on the grid, or a few grids' worth around it
+    fn random_point<R: Rng>(rng: &mut R, dims: DPoint2) -> DPoint2 {
+        DPoint2::new(rng.gen_range(-3 * dims.x, 4 * dims.x), rng.gen_range(-3 * dims.y, 4 * dims.y))
+    }
+
+    fn modulo(v: i32, n: i32) -> i32 {
+        ((v % n) + n) % n
+    }
+
+    fn numbered(dims: DPoint2) -> TotalGrid<(usize, usize)> {
+        TotalGrid::new_from_func(dims, &mut |x, y| (x, y))
+    }
+
+    fn sorted(mut v: Vec<DPoint2>) -> Vec<DPoint2> {
+        v.sort_by_key(|pt| (pt.y, pt.x));
+        v
+    }
+
+    #[test]
+    fn put_and_get_every_cell() {
+        for dims in shapes() {
+            let (w, h) = (dims.x, dims.y);
+            let mut grid = TotalGrid::new_filled(dims, (0, 0));
+            for y in 0..h as usize {
+                for x in 0..w as usize {
+                    grid.put(x, y, (x, y));
+                }
+            }
+            assert_eq!(grid, numbered(dims));
+            for y in 0..h as usize {
+                for x in 0..w as usize {
+                    assert_eq!(*grid.get(x, y), (x, y));
+                    assert_eq!(grid[DPoint2::new(x as i32, y as i32)], (x, y));
+                    *grid.get_mut(x, y) = (y, x);
+                }
+            }
+            assert_eq!(grid.maybe_get(w as usize, 0), None);
+            assert_eq!(grid.maybe_get(0, h as usize), None);
+            assert_eq!(grid.column(w - 1).count(), h as usize);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn get_past_the_right_edge_panics() {
+        // (3, 0) would be (0, 1)
+        numbered(DPoint2::new(3, 2)).get(3, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn put_past_the_right_edge_panics() {
+        numbered(DPoint2::new(3, 2)).put(3, 0, (9, 9));
+    }
+
+    #[test]
+    #[should_panic]
+    fn column_past_the_right_edge_panics() {
+        numbered(DPoint2::new(3, 2)).column(3);
+    }
+
+    #[test]
+    fn iterators_visit_each_cell_once() {
+        for dims in shapes() {
+            let (w, h) = (dims.x, dims.y);
+            let grid = numbered(dims);
+            let cells: Vec<DPoint2> = grid.cell_iterator().collect();
+            let visited: Vec<(DPoint2, (usize, usize))> = grid.into_iter().map(|(pt, e)| (pt, *e)).collect();
+            assert_eq!(cells.len(), (w * h) as usize);
+            assert_eq!(cells.iter().collect::<HashSet<_>>().len(), cells.len());
+            // in row order, each with its own element
+            assert_eq!(cells, sorted(cells.clone()));
+            assert_eq!(visited.iter().map(|&(pt, _)| pt).collect::<Vec<_>>(), cells);
+            for (pt, e) in visited {
+                assert_eq!(e, (pt.x as usize, pt.y as usize));
+            }
+            assert_eq!(grid.rows().count(), h as usize);
+            assert_eq!(grid.columns().count(), w as usize);
+        }
+    }
+
+    #[test]
+    fn neighbours_on_a_bounded_grid() {
+        let grid = numbered(DPoint2::new(4, 3));
+        let pts = |v: &[(i32, i32)]| sorted(v.iter().map(|&(x, y)| DPoint2::new(x, y)).collect());
+        assert_eq!(sorted(grid.neighbours4(DPoint2::new(1, 1))), pts(&[(1, 0), (0, 1), (2, 1), (1, 2)]));
+        assert_eq!(sorted(grid.neighbours8(DPoint2::new(1, 1))).len(), 8);
+        assert_eq!(sorted(grid.neighbours4(DPoint2::new(0, 0))), pts(&[(1, 0), (0, 1)]));
+        assert_eq!(sorted(grid.neighbours8(DPoint2::new(3, 2))), pts(&[(3, 1), (2, 1), (2, 2)]));
+        // up, right, down, left, then the diagonals
+        assert_eq!(grid.neighbours8(DPoint2::new(1, 1))[..4], [
+            DPoint2::new(1, 0), DPoint2::new(2, 1), DPoint2::new(1, 2), DPoint2::new(0, 1),
+        ]);
+    }
+
+    #[test]
+    fn neighbours_across_wrapping_edges() {
+        let torus = numbered(DPoint2::new(4, 3)).with_topology(Topology::Torus);
+        assert_eq!(torus.neighbours4(DPoint2::new(0, 0)).len(), 4);
+        assert!(torus.neighbours4(DPoint2::new(0, 0)).contains(&DPoint2::new(3, 0)));
+        assert!(torus.neighbours8(DPoint2::new(0, 0)).contains(&DPoint2::new(3, 2)));
+
+        // over the pole, half way around
+        let sphere = numbered(DPoint2::new(4, 3)).with_topology(Topology::Sphere);
+        assert!(sphere.neighbours4(DPoint2::new(0, 0)).contains(&DPoint2::new(2, 0)));
+
+        // on a 1x1 torus everything is the cell itself
+        let tiny = numbered(DPoint2::new(1, 1)).with_topology(Topology::Torus);
+        assert!(tiny.neighbours8(DPoint2::new(0, 0)).is_empty());
+        // and on 2x1 the one other cell, once
+        let pair = numbered(DPoint2::new(2, 1)).with_topology(Topology::WrapX);
+        assert_eq!(pair.neighbours8(DPoint2::new(0, 0)), vec![DPoint2::new(1, 0)]);
+    }
+
+    #[test]
+    fn wrap_on_every_shape_and_topology() {
+        let mut rng = Isaac64Rng::from_seed(&[48]);
+        for dims in shapes() {
+            for topology in TOPOLOGIES.iter() {
+                for _ in 0..50 {
+                    let pt = random_point(&mut rng, dims);
+                    let inside = pt.x >= 0 && pt.y >= 0 && pt.x < dims.x && pt.y < dims.y;
+                    let y_inside = pt.y >= 0 && pt.y < dims.y;
+                    let same_column = DPoint2::new(modulo(pt.x, dims.x), pt.y);
+                    let wrapped = topology.wrap(dims, pt);
+                    if inside {
+                        assert_eq!(wrapped, Some(pt));
+                    }
+                    if let Some(w) = wrapped {
+                        assert_eq!(topology.wrap(dims, w), Some(w), "{:?} {:?} {:?}", topology, dims, pt);
+                    }
+                    let expected = match *topology {
+                        Topology::Bounded => if inside {Some(pt)} else {None},
+                        Topology::WrapX => if y_inside {Some(same_column)} else {None},
+                        Topology::Torus => Some(DPoint2::new(same_column.x, modulo(pt.y, dims.y))),
+                        Topology::Sphere if dims.x % 2 == 1 => if y_inside {Some(same_column)} else {None},
+                        Topology::Sphere => {
+                            // every two trips over the poles take you back where you were
+                            let y = modulo(pt.y, 2 * dims.y);
+                            if y < dims.y {
+                                Some(DPoint2::new(same_column.x, y))
+                            } else {
+                                Some(DPoint2::new(modulo(pt.x + dims.x / 2, dims.x), 2 * dims.y - 1 - y))
+                            }
+                        },
+                    };
+                    assert_eq!(wrapped, expected, "{:?} {:?} {:?}", topology, dims, pt);
+                }
+            }
+        }
+        assert_eq!(Topology::Torus.wrap(DPoint2::new(0, 3), DPoint2::new(0, 0)), None);
+    }
+
+    #[test]
+    fn neighbours_on_every_shape_and_topology() {
+        let mut rng = Isaac64Rng::from_seed(&[49]);
+        for dims in shapes() {
+            for topology in TOPOLOGIES.iter() {
+                let grid = numbered(dims).with_topology(*topology);
+                for hood in [Neighbourhood::Four, Neighbourhood::Eight].iter() {
+                    for _ in 0..10 {
+                        let pt = DPoint2::new(rng.gen_range(0, dims.x), rng.gen_range(0, dims.y));
+                        let found = grid.neighbours(pt, *hood);
+                        let unique: HashSet<DPoint2> = found.iter().cloned().collect();
+                        assert_eq!(unique.len(), found.len(), "{:?} {:?} {:?}: {:?}", topology, dims, pt, found);
+                        assert!(!unique.contains(&pt));
+                        assert!(found.len() <= hood.offsets().len());
+                        let expected: HashSet<DPoint2> = hood.offsets().iter()
+                            .filter_map(|&(dx, dy)| grid.wrap(DPoint2::new(pt.x + dx, pt.y + dy)))
+                            .filter(|n| *n != pt)
+                            .collect();
+                        assert_eq!(unique, expected);
+                        // and each of them has `pt` as a neighbour too
+                        for n in found.iter() {
+                            assert!(grid.neighbours(*n, *hood).contains(&pt), "{:?} {:?} {:?} {:?}", topology, dims, pt, n);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn views_are_relative_to_their_corner() {
+        let grid = numbered(DPoint2::new(5, 4));
+        let view = grid.view(DPoint2::new(1, 2), DPoint2::new(3, 2)).unwrap();
+        assert_eq!(view.at(DPoint2::new(0, 0)), Some(&(1, 2)));
+        assert_eq!(view.at(DPoint2::new(2, 1)), Some(&(3, 3)));
+        assert_eq!(view.at(DPoint2::new(3, 0)), None);
+        assert_eq!(view.row(1), &[(1, 3), (2, 3), (3, 3)]);
+        assert_eq!(view.iter().count(), 6);
+        let copy = view.to_grid();
+        assert_eq!(copy.get_dimensions(), DPoint2::new(3, 2));
+        assert_eq!(*copy.get(2, 0), (3, 2));
+
+        assert!(grid.view(DPoint2::new(3, 0), DPoint2::new(3, 1)).is_none());
+        assert!(grid.view(DPoint2::new(0, 0), DPoint2::new(0, 1)).is_none());
+        assert!(grid.view(DPoint2::new(0, 0), DPoint2::new(5, 4)).is_some());
+    }
+
+    #[test]
+    fn map_and_zip_with_keep_the_shape() {
+        let grid = numbered(DPoint2::new(3, 2)).with_topology(Topology::WrapX);
+        let sums = grid.map(|pt, &(x, y)| {
+            assert_eq!((pt.x as usize, pt.y as usize), (x, y));
+            x + 10 * y
+        });
+        assert_eq!(sums.get_dimensions(), grid.get_dimensions());
+        assert_eq!(sums.get_topology(), Topology::WrapX);
+        assert_eq!(*sums.get(2, 1), 12);
+
+        let zipped = grid.zip_with(&sums, |_, &(x, _), s| s - x).unwrap();
+        assert_eq!(zipped.into_elements(), vec![0, 0, 0, 10, 10, 10]);
+        assert!(grid.zip_with(&numbered(DPoint2::new(2, 3)), |_, _, _| ()).is_none());
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+use ::points::DPoint2;
+use super::grid::{TotalGrid,Topology,Neighbourhood};
+
+/*
+Questions about whole grids: which cells are connected to which, and how far
+every cell is from the nearest cell of some kind. eg: lakes are the
+components of water, and the shore is where the distance to water is 1.
+
+Everything here follows the grid's topology, so a region may continue across
+a wrapping edge, and the nearest water may be on the other side of it.
+*/
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum DistanceMetric {
+    // steps up, down, left or right
+    Manhattan,
+    // as the crow flies, between cell centres
+    Euclidean,
+}
+
+// the connected regions of a grid, each with a label 0 .. count.
+// labels are handed out in row order of each region's first cell
+#[derive(Debug,Clone,PartialEq)]
+pub struct Components {
+    labels: TotalGrid<Option<usize>>,
+    sizes: Vec<usize>,
+}
+
+impl Components {
+    // None for cells in no region
+    pub fn get_labels(&self) -> &TotalGrid<Option<usize>> {&self.labels}
+    pub fn label_at(&self, pt: DPoint2) -> Option<usize> {self.labels.at(pt).cloned().and_then(|l| l)}
+    pub fn count(&self) -> usize {self.sizes.len()}
+    // cells in each region, by label
+    pub fn get_sizes(&self) -> &[usize] {&self.sizes}
+
+    // the label of the region with the most cells. ties go to the lowest label
+    pub fn largest(&self) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        for (label, size) in self.sizes.iter().enumerate() {
+            if best.map(|b| *size > self.sizes[b]).unwrap_or(true) {
+                best = Some(label);
+            }
+        }
+        best
+    }
+
+    // in row order
+    pub fn cells_of(&self, label: usize) -> Vec<DPoint2> {
+        self.labels.into_iter()
+        .filter(|&(_, l)| *l == Some(label))
+        .map(|(pt, _)| pt)
+        .collect()
+    }
+}
+
+// breadth first from `start` over cells for which `inside` holds, marking them in `marks`
+fn flood<F>(marks: &mut TotalGrid<Option<usize>>, start: DPoint2, label: usize, hood: Neighbourhood, inside: &F) -> usize
+where F: Fn(DPoint2) -> bool {
+    let mut count = 1;
+    let mut queue = VecDeque::new();
+    marks[start] = Some(label);
+    queue.push_back(start);
+    while let Some(at) = queue.pop_front() {
+        for n in marks.neighbours(at, hood) {
+            if marks[n].is_none() && inside(n) {
+                marks[n] = Some(label);
+                count += 1;
+                queue.push_back(n);
+            }
+        }
+    }
+    count
+}
+
+// 1D squared euclidean distance transform (Felzenszwalb & Huttenlocher):
+// out[q] = min over p of (q-p)^2 + f[p], where None is infinitely far
+fn squared_distances_1d(f: &[Option<f64>]) -> Vec<Option<f64>> {
+    // the parabolas making up the lower envelope, and where each takes over
+    let mut vertices: Vec<usize> = vec![];
+    let mut starts: Vec<f64> = vec![];
+    for (q, fq) in f.iter().enumerate() {
+        let fq = match *fq {
+            Some(fq) => fq,
+            None => continue,
+        };
+        let qf = q as f64;
+        loop {
+            let v = match vertices.last() {
+                Some(v) => *v,
+                None => {
+                    vertices.push(q);
+                    starts.push(::std::f64::NEG_INFINITY);
+                    break
+                },
+            };
+            let vf = v as f64;
+            let fv = f[v].unwrap();
+            // where the parabolas from v and q cross
+            let s = ((fq + qf * qf) - (fv + vf * vf)) / (2.0 * qf - 2.0 * vf);
+            if s <= *starts.last().unwrap() {
+                vertices.pop();
+                starts.pop();
+            } else {
+                vertices.push(q);
+                starts.push(s);
+                break
+            }
+        }
+    }
+    if vertices.is_empty() {return vec![None; f.len()]}
+    let mut k = 0;
+    (0..f.len()).map(|q| {
+        let qf = q as f64;
+        while k + 1 < starts.len() && starts[k + 1] < qf {
+            k += 1;
+        }
+        let v = vertices[k];
+        let d = qf - v as f64;
+        Some(d * d + f[v].unwrap())
+    }).collect()
+}
+
+// as above, but the ends of `f` meet
+fn squared_distances_1d_wrapped(f: &[Option<f64>]) -> Vec<Option<f64>> {
+    let n = f.len();
+    // three copies side by side: the middle one sees the nearest of each
+    let tripled: Vec<Option<f64>> = f.iter().chain(f.iter()).chain(f.iter()).cloned().collect();
+    squared_distances_1d(&tripled)[n..2 * n].to_vec()
+}
+
+impl<T> TotalGrid<T> {
+    // the cells reachable from `start` without leaving cells where `pred` holds.
+    // all false if it doesn't hold at `start` (or `start` is off the grid)
+    pub fn flood_fill<F>(&self, start: DPoint2, hood: Neighbourhood, pred: F) -> TotalGrid<bool>
+    where F: Fn(&T) -> bool {
+        let mut marks = self.map(|_, _| None);
+        if self.at(start).map(|e| pred(e)).unwrap_or(false) {
+            flood(&mut marks, start, 0, hood, &|pt| pred(&self[pt]));
+        }
+        marks.map(|_, m| m.is_some())
+    }
+
+    // the connected regions of cells where `pred` holds
+    pub fn components<F>(&self, hood: Neighbourhood, pred: F) -> Components
+    where F: Fn(&T) -> bool {
+        let mut labels = self.map(|_, _| None);
+        let mut sizes = vec![];
+        for pt in self.cell_iterator() {
+            if labels[pt].is_some() || !pred(&self[pt]) {continue}
+            let label = sizes.len();
+            sizes.push(flood(&mut labels, pt, label, hood, &|pt| pred(&self[pt])));
+        }
+        Components {labels: labels, sizes: sizes}
+    }
+
+    // how far each cell is from the nearest cell where `pred` holds (0 for those cells).
+    // all None if it holds nowhere
+    pub fn distances<F>(&self, metric: DistanceMetric, pred: F) -> TotalGrid<Option<f32>>
+    where F: Fn(&T) -> bool {
+        match metric {
+            DistanceMetric::Manhattan => self.manhattan_distances(pred).map(|_, d| d.map(|d| d as f32)),
+            DistanceMetric::Euclidean => self.euclidean_distances(pred),
+        }
+    }
+
+    // breadth first from every cell where `pred` holds at once
+    pub fn manhattan_distances<F>(&self, pred: F) -> TotalGrid<Option<u32>>
+    where F: Fn(&T) -> bool {
+        let mut dists = self.map(|_, e| if pred(e) {Some(0)} else {None});
+        let mut queue: VecDeque<DPoint2> = dists.cell_iterator().filter(|pt| dists[*pt].is_some()).collect();
+        while let Some(at) = queue.pop_front() {
+            let d = dists[at].unwrap() + 1;
+            for n in dists.neighbours4(at) {
+                if dists[n].is_none() {
+                    dists[n] = Some(d);
+                    queue.push_back(n);
+                }
+            }
+        }
+        dists
+    }
+
+    // exact: a distance transform along each row, then along each column
+    pub fn euclidean_distances<F>(&self, pred: F) -> TotalGrid<Option<f32>>
+    where F: Fn(&T) -> bool {
+        let dims = self.get_dimensions();
+        let topology = self.get_topology();
+        let wraps_x = topology != Topology::Bounded;
+        let rows: Vec<Vec<Option<f64>>> = self.rows().map(|row| {
+            let f: Vec<Option<f64>> = row.iter().map(|e| if pred(e) {Some(0.0)} else {None}).collect();
+            if wraps_x {squared_distances_1d_wrapped(&f)} else {squared_distances_1d(&f)}
+        }).collect();
+
+        let mut squared = self.map(|_, _| None);
+        for x in 0..dims.x {
+            let column: Vec<Option<f64>> = rows.iter().map(|row| row[x as usize]).collect();
+            let done = match topology {
+                Topology::Torus => squared_distances_1d_wrapped(&column),
+                Topology::Sphere if dims.x % 2 == 0 => {
+                    // the column carries on over each pole, down the column half way around
+                    let h = dims.y as usize;
+                    let over: Vec<Option<f64>> = rows.iter().map(|row| row[((x + dims.x / 2) % dims.x) as usize]).collect();
+                    let unfolded: Vec<Option<f64>> = over.iter().rev()
+                        .chain(column.iter())
+                        .chain(over.iter().rev())
+                        .cloned().collect();
+                    squared_distances_1d(&unfolded)[h..2 * h].to_vec()
+                },
+                _ => squared_distances_1d(&column),
+            };
+            for (y, d) in done.into_iter().enumerate() {
+                squared[DPoint2::new(x, y as i32)] = d;
+            }
+        }
+        squared.map(|_, d| d.map(|d| d.sqrt() as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::{SeedableRng,Rng,Isaac64Rng};
+    use super::*;
+
+    const TOPOLOGIES: [Topology; 4] = [Topology::Bounded, Topology::WrapX, Topology::Torus, Topology::Sphere];
+
+    // mostly false, odd and even widths
+    fn random_grids(seed: u64, topology: Topology) -> Vec<TotalGrid<bool>> {
+        let mut rng = Isaac64Rng::from_seed(&[seed]);
+        (0..20).map(|_| {
+            let dims = DPoint2::new(rng.gen_range(1, 10), rng.gen_range(1, 10));
+            TotalGrid::new_from_func(dims, &mut |_, _| rng.gen_weighted_bool(6)).with_topology(topology)
+        }).collect()
+    }
+
+    // '#' is true
+    fn drawn(rows: &[&str], topology: Topology) -> TotalGrid<bool> {
+        let dims = DPoint2::new(rows[0].len() as i32, rows.len() as i32);
+        TotalGrid::new_from_func(dims, &mut |x, y| rows[y].as_bytes()[x] == b'#').with_topology(topology)
+    }
+
+    // the nearest true cell each way around the grid, by trying them all
+    fn brute_force<F>(grid: &TotalGrid<bool>, pt: DPoint2, length: F) -> Option<f32>
+    where F: Fn(u32, u32) -> f32 {
+        let topology = grid.get_topology();
+        grid.cell_iterator().filter(|c| grid[*c]).flat_map(|c| {
+            topology.separations(grid.get_dimensions(), pt, c).into_iter().map(|(dx, dy)| length(dx, dy))
+        }).fold(None, |best: Option<f32>, d| Some(best.map(|b| b.min(d)).unwrap_or(d)))
+    }
+
+    #[test]
+    fn distances_match_brute_force() {
+        for (t, topology) in TOPOLOGIES.iter().enumerate() {
+            for grid in random_grids(t as u64, *topology) {
+                let manhattan = grid.distances(DistanceMetric::Manhattan, |e| *e);
+                let euclidean = grid.distances(DistanceMetric::Euclidean, |e| *e);
+                for pt in grid.cell_iterator() {
+                    let expected = brute_force(&grid, pt, |dx, dy| (dx + dy) as f32);
+                    assert_eq!(manhattan[pt], expected, "{:?} {:?} at {:?}", topology, grid.get_dimensions(), pt);
+                    let expected = brute_force(&grid, pt, |dx, dy| ((dx * dx + dy * dy) as f32).sqrt());
+                    match (euclidean[pt], expected) {
+                        (Some(a), Some(b)) => assert!((a - b).abs() < 1e-4, "{:?} {:?} at {:?}: {} != {}",
+                            topology, grid.get_dimensions(), pt, a, b),
+                        (a, b) => assert_eq!(a, b),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn distances_to_nothing_are_none() {
+        for topology in TOPOLOGIES.iter() {
+            let grid = drawn(&["....", "....", "...."], *topology);
+            for metric in [DistanceMetric::Manhattan, DistanceMetric::Euclidean].iter() {
+                assert!(grid.distances(*metric, |e| *e).into_iter().all(|(_, d)| d.is_none()));
+            }
+        }
+    }
+
+    #[test]
+    fn components_are_labelled_in_row_order() {
+        let rows = ["#..#", "#...", "...#"];
+        let bounded = drawn(&rows, Topology::Bounded).components(Neighbourhood::Four, |e| *e);
+        assert_eq!(bounded.get_sizes(), &[2, 1, 1]);
+        assert_eq!(bounded.cells_of(0), vec![DPoint2::new(0, 0), DPoint2::new(0, 1)]);
+        assert_eq!(bounded.label_at(DPoint2::new(3, 0)), Some(1));
+        assert_eq!(bounded.label_at(DPoint2::new(3, 2)), Some(2));
+        assert_eq!(bounded.label_at(DPoint2::new(1, 1)), None);
+        assert_eq!(bounded.label_at(DPoint2::new(9, 9)), None);
+
+        // across the left and right edges
+        let wrapped = drawn(&rows, Topology::WrapX).components(Neighbourhood::Four, |e| *e);
+        assert_eq!(wrapped.get_sizes(), &[3, 1]);
+        assert_eq!(wrapped.cells_of(0), vec![DPoint2::new(0, 0), DPoint2::new(3, 0), DPoint2::new(0, 1)]);
+        // and over the top and bottom too
+        let torus = drawn(&rows, Topology::Torus).components(Neighbourhood::Four, |e| *e);
+        assert_eq!(torus.get_sizes(), &[4]);
+        // (1,1) only touches (0,0) diagonally
+        let diagonal = drawn(&["#...", ".#..", "...#"], Topology::Bounded).components(Neighbourhood::Eight, |e| *e);
+        assert_eq!(diagonal.get_sizes(), &[2, 1]);
+
+        for (t, topology) in TOPOLOGIES.iter().enumerate() {
+            for grid in random_grids(10 + t as u64, *topology) {
+                let c = grid.components(Neighbourhood::Four, |e| *e);
+                let firsts: Vec<DPoint2> = (0..c.count()).map(|l| c.cells_of(l)[0]).collect();
+                let mut in_row_order = firsts.clone();
+                in_row_order.sort_by_key(|pt| (pt.y, pt.x));
+                assert_eq!(firsts, in_row_order);
+                for l in 0..c.count() {
+                    assert_eq!(c.cells_of(l).len(), c.get_sizes()[l]);
+                }
+                assert_eq!(c.get_sizes().iter().sum::<usize>(), grid.into_iter().filter(|&(_, e)| *e).count());
+            }
+        }
+    }
+
+    #[test]
+    fn largest_prefers_the_lowest_label() {
+        let c = drawn(&["##.##", ".....", "..###"], Topology::Bounded).components(Neighbourhood::Four, |e| *e);
+        assert_eq!(c.get_sizes(), &[2, 2, 3]);
+        assert_eq!(c.largest(), Some(2));
+        let c = drawn(&["##.##"], Topology::Bounded).components(Neighbourhood::Four, |e| *e);
+        assert_eq!(c.largest(), Some(0));
+        // joined across the edge
+        let c = drawn(&["##.##"], Topology::WrapX).components(Neighbourhood::Four, |e| *e);
+        assert_eq!((c.count(), c.largest()), (1, Some(0)));
+        let c = drawn(&["....."], Topology::Bounded).components(Neighbourhood::Four, |e| *e);
+        assert_eq!((c.count(), c.largest()), (0, None));
+    }
+
+    #[test]
+    fn flood_fill_stays_in_its_region() {
+        let grid = drawn(&["#..#", "#...", "...#"], Topology::WrapX);
+        let filled = grid.flood_fill(DPoint2::new(0, 1), Neighbourhood::Four, |e| *e);
+        let expected = grid.components(Neighbourhood::Four, |e| *e).get_labels().map(|_, l| *l == Some(0));
+        assert_eq!(filled, expected);
+
+        // starting where the predicate fails, or off the grid, fills nothing
+        let nothing = grid.map(|_, _| false);
+        assert_eq!(grid.flood_fill(DPoint2::new(1, 1), Neighbourhood::Four, |e| *e), nothing);
+        assert_eq!(grid.flood_fill(DPoint2::new(0, 5), Neighbourhood::Four, |e| *e), nothing);
+        assert_eq!(grid.flood_fill(DPoint2::new(-1, 0), Neighbourhood::Four, |e| *e), nothing);
+    }
+}
@@ -0,0 +1,314 @@
+use super::Material;
+use super::grid::TotalGrid;
+use ::points::*;
+use ::portals::UniquePoint;
+use ::primitive::{Primitive,AppliesDiff};
+use ::rand::{SeedableRng,Rng,Isaac64Rng};
+use std::collections::VecDeque;
+
+const MIN_SIDE : i32 = 24;
+const MAX_EXTRA_SIDE : f32 = 72.0;
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub enum Race {
+    Human, Dwarf, Goblin,
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub enum Tile {
+    Wall, Floor, Entrance,
+}
+
+impl Tile {
+    pub fn is_walkable(self) -> bool {
+        self != Tile::Wall
+    }
+}
+
+#[derive(Debug,Copy,Clone,Serialize,Deserialize)]
+pub struct InteriorPrimitive {
+    seed: u64,
+    temp: f32,
+    race: Race,
+    size: f32,
+    parent_door: UniquePoint,
+}
+
+impl InteriorPrimitive {
+    // size in (0.0, 1.0). parent_door is where the entrance leads back out to
+    pub fn new(seed: u64, temp: f32, race: Race, size: f32, parent_door: UniquePoint) -> InteriorPrimitive {
+        InteriorPrimitive {
+            seed: seed,
+            temp: temp,
+            race: race,
+            size: size,
+            parent_door: parent_door,
+        }
+    }
+}
+
+impl Primitive<Interior, InteriorDiff> for InteriorPrimitive {
+    fn generate_new(&self) -> Interior {
+        Interior::generate(self)
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Serialize,Deserialize)]
+pub enum InteriorDiff {
+    SetTile(DPoint2, Tile),
+}
+
+// a walkable cell of an interior that acts as a portal back into the parent Location
+#[derive(Debug,Copy,Clone)]
+pub struct InteriorEntrance {
+    cell: DPoint2,
+    leads_to: UniquePoint,
+}
+
+impl InteriorEntrance {
+    pub fn get_cell(&self) -> DPoint2 {self.cell}
+    pub fn leads_to(&self) -> UniquePoint {self.leads_to}
+}
+
+pub struct Interior {
+    tiles: TotalGrid<Tile>,
+    wall_material: Material,
+    entrances: Vec<InteriorEntrance>,
+}
+
+#[derive(Copy,Clone)]
+struct Room {
+    tl: DPoint2,
+    br: DPoint2,
+}
+
+impl Room {
+    fn centre(&self) -> DPoint2 {
+        DPoint2::new((self.tl.x + self.br.x) / 2, (self.tl.y + self.br.y) / 2)
+    }
+
+    fn overlaps_with(&self, other: &Room, margin: i32) -> bool {
+        !(self.tl.x - margin > other.br.x
+        || self.br.x + margin < other.tl.x
+        || self.tl.y - margin > other.br.y
+        || self.br.y + margin < other.tl.y)
+    }
+}
+
+impl Interior {
+    pub fn generate(prim: &InteriorPrimitive) -> Interior {
+        let mut rng = Isaac64Rng::from_seed(&[prim.seed]);
+        let side = MIN_SIDE + (prim.size.max(0.0).min(1.0) * MAX_EXTRA_SIDE) as i32;
+        let dimensions = DPoint2::new(side, side);
+        let mut tiles = TotalGrid::new_from_func(dimensions, &mut |_, _| Tile::Wall);
+        let start = match prim.race {
+            Race::Goblin => Self::dig_cave(&mut tiles, &mut rng),
+            Race::Human => Self::dig_rooms(&mut tiles, &mut rng, [4, 7]),
+            Race::Dwarf => Self::dig_rooms(&mut tiles, &mut rng, [6, 12]),
+        };
+
+        // tunnel straight down from the start until reaching the bottom edge
+        let door = DPoint2::new(start.x, side - 1);
+        for y in start.y..side {
+            tiles.put(door.x as usize, y as usize, Tile::Floor);
+        }
+        tiles.put(door.x as usize, door.y as usize, Tile::Entrance);
+
+        let wall_material = if prim.temp < 0.2 {Material::Ice}
+            else if prim.temp < 0.6 {Material::Rock}
+            else {Material::DarkRock};
+        Interior {
+            tiles: tiles,
+            wall_material: wall_material,
+            entrances: vec![InteriorEntrance {cell: door, leads_to: prim.parent_door}],
+        }
+    }
+
+    // rooms joined by L-shaped corridors. returns the centre of the first room
+    fn dig_rooms<R: Rng>(tiles: &mut TotalGrid<Tile>, rng: &mut R, room_side: [i32;2]) -> DPoint2 {
+        let side = tiles.get_width();
+        let mut rooms: Vec<Room> = vec![];
+        for _ in 0..(side * 2) {
+            let (w, h) = (rng.gen_range(room_side[0], room_side[1]+1), rng.gen_range(room_side[0], room_side[1]+1));
+            if w + 2 >= side || h + 2 >= side {continue}
+            let tl = DPoint2::new(rng.gen_range(1, side-w-1), rng.gen_range(1, side-h-1));
+            let room = Room {tl: tl, br: DPoint2::new(tl.x+w-1, tl.y+h-1)};
+            if rooms.iter().any(|r| r.overlaps_with(&room, 2)) {continue}
+            rooms.push(room);
+        }
+        if rooms.is_empty() {
+            let c = side / 2;
+            rooms.push(Room {tl: DPoint2::new(c-1, c-1), br: DPoint2::new(c+1, c+1)});
+        }
+        for room in rooms.iter() {
+            for y in room.tl.y..(room.br.y+1) {
+                for x in room.tl.x..(room.br.x+1) {
+                    tiles.put(x as usize, y as usize, Tile::Floor);
+                }
+            }
+        }
+        for pair in rooms.windows(2) {
+            let (a, b) = (pair[0].centre(), pair[1].centre());
+            let bend = if rng.gen() {DPoint2::new(b.x, a.y)} else {DPoint2::new(a.x, b.y)};
+            Self::dig_line(tiles, a, bend);
+            Self::dig_line(tiles, bend, b);
+        }
+        rooms[0].centre()
+    }
+
+    fn dig_line(tiles: &mut TotalGrid<Tile>, from: DPoint2, to: DPoint2) {
+        let (dx, dy) = ((to.x - from.x).signum(), (to.y - from.y).signum());
+        let mut at = from;
+        loop {
+            tiles.put(at.x as usize, at.y as usize, Tile::Floor);
+            if at == to {break}
+            at = DPoint2::new(at.x + dx, at.y + dy);
+        }
+    }
+
+    // cellular automaton caves. only the largest open region survives.
+    // returns the floor cell of that region closest to the bottom edge
+    fn dig_cave<R: Rng>(tiles: &mut TotalGrid<Tile>, rng: &mut R) -> DPoint2 {
+        let side = tiles.get_width();
+        let on_border = |x: i32, y: i32| x == 0 || y == 0 || x == side-1 || y == side-1;
+        let mut open = TotalGrid::new_from_func(tiles.get_dimensions(), &mut |x, y| {
+            !on_border(x as i32, y as i32) && rng.gen::<f32>() > 0.48
+        });
+        for _ in 0..4 {
+            open = TotalGrid::new_from_func(open.get_dimensions(), &mut |x, y| {
+                if on_border(x as i32, y as i32) {return false}
+                // the cell itself counts too
+                let mut walls = 0;
+                for ny in (y-1)..(y+2) {
+                    for nx in (x-1)..(x+2) {
+                        if !*open.get(nx, ny) {walls += 1}
+                    }
+                }
+                walls < 5
+            });
+        }
+
+        // flood every open region, remembering the biggest
+        let mut visited = TotalGrid::new_from_func(open.get_dimensions(), &mut |_, _| false);
+        let mut biggest: Vec<DPoint2> = vec![];
+        for start in open.cell_iterator() {
+            let (sx, sy) = (start.x as usize, start.y as usize);
+            if !*open.get(sx, sy) || *visited.get(sx, sy) {continue}
+            let mut members = vec![];
+            let mut queue = VecDeque::new();
+            visited.put(sx, sy, true);
+            queue.push_back(start);
+            while let Some(at) = queue.pop_front() {
+                members.push(at);
+                for n in [at.shift_x(1), at.shift_x(-1), at.shift_y(1), at.shift_y(-1)].iter() {
+                    let (nx, ny) = (n.x as usize, n.y as usize);
+                    if *open.get(nx, ny) && !*visited.get(nx, ny) {
+                        visited.put(nx, ny, true);
+                        queue.push_back(*n);
+                    }
+                }
+            }
+            if members.len() > biggest.len() {
+                biggest = members;
+            }
+        }
+
+        if biggest.is_empty() {
+            biggest.push(DPoint2::new(side / 2, side / 2));
+        }
+        for m in biggest.iter() {
+            tiles.put(m.x as usize, m.y as usize, Tile::Floor);
+        }
+        *biggest.iter().max_by_key(|m| m.y).unwrap()
+    }
+
+    pub fn get_tiles(&self) -> &TotalGrid<Tile> {&self.tiles}
+    pub fn get_wall_material(&self) -> Material {self.wall_material}
+    pub fn get_entrances(&self) -> &[InteriorEntrance] {&self.entrances}
+
+    pub fn tile_at(&self, cell: DPoint2) -> Tile {
+        *self.tiles.get(cell.x as usize, cell.y as usize)
+    }
+}
+
+impl AppliesDiff<InteriorDiff> for Interior {
+    // diffs outside the interior are ignored
+    fn apply(&mut self, diff: &InteriorDiff) {
+        match diff {
+            &InteriorDiff::SetTile(cell, tile) => {
+                if let Some(t) = self.tiles.at_mut(cell) {
+                    *t = tile;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grid::Neighbourhood;
+    use super::*;
+
+    const RACES: [Race; 3] = [Race::Human, Race::Dwarf, Race::Goblin];
+
+    fn door() -> UniquePoint {
+        UniquePoint::new(4, CPoint2::new(12.0, 30.5))
+    }
+
+    fn interior(seed: u64, race: Race, size: f32) -> Interior {
+        InteriorPrimitive::new(seed, 0.5, race, size, door()).generate_new()
+    }
+
+    #[test]
+    fn same_seed_same_interior() {
+        for race in RACES.iter() {
+            let a = interior(9, *race, 0.4);
+            assert!(a.get_tiles() == interior(9, *race, 0.4).get_tiles());
+            assert!(a.get_tiles() != interior(10, *race, 0.4).get_tiles(), "{:?}", race);
+        }
+    }
+
+    #[test]
+    fn every_floor_is_reachable_from_the_entrance() {
+        for race in RACES.iter() {
+            for tenths in 0..11 {
+                let size = tenths as f32 / 10.0;
+                for seed in 0..3 {
+                    let i = interior(seed, *race, size);
+                    let tiles = i.get_tiles();
+                    let entrance = i.get_entrances()[0].get_cell();
+                    let reached = tiles.flood_fill(entrance, Neighbourhood::Four, |t| t.is_walkable());
+                    for cell in tiles.cell_iterator() {
+                        assert_eq!(reached[cell], tiles[cell].is_walkable(), "{:?} {} {}: {:?}", race, size, seed, cell);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_entrance_leads_to_the_parent_door() {
+        for race in RACES.iter() {
+            let i = interior(2, *race, 0.7);
+            assert_eq!(i.get_entrances().len(), 1);
+            let entrance = i.get_entrances()[0];
+            assert_eq!(entrance.leads_to(), door());
+            assert_eq!(i.tile_at(entrance.get_cell()), Tile::Entrance);
+            // on the bottom edge
+            assert_eq!(entrance.get_cell().y, i.get_tiles().get_height() - 1);
+        }
+    }
+
+    #[test]
+    fn diffs_outside_the_interior_are_ignored() {
+        let mut i = interior(2, Race::Human, 0.3);
+        let before = i.get_tiles().clone();
+        let side = before.get_width();
+        for cell in [DPoint2::new(-1, 0), DPoint2::new(0, -1), DPoint2::new(side, 0), DPoint2::new(0, side)].iter() {
+            i.apply(&InteriorDiff::SetTile(*cell, Tile::Floor));
+        }
+        assert!(*i.get_tiles() == before);
+        i.apply(&InteriorDiff::SetTile(DPoint2::new(0, 0), Tile::Floor));
+        assert_eq!(i.tile_at(DPoint2::new(0, 0)), Tile::Floor);
+    }
+}
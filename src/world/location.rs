@@ -1,39 +1,388 @@
-use super::{Material};
+use super::{Material,MaterialThresholds,PointSampleData};
 use super::zones::{Zone};
 use ::points::*;
 use super::grid::TotalGrid;
+use super::placement::{Placements,ObjectKind};
+use ::primitive::{Primitive,AppliesDiff};
+use ::procedural::NoiseField;
+use ::rand::{SeedableRng,Isaac64Rng};
 
 const METERS_PER_ZONE_SAMPLE : i32 = 50;
 
+// how far the fine detail may push a cell away from the interpolated zone samples
+const HEIGHT_DETAIL : f32 = 0.012;
+const SLOPE_DETAIL : f32 = 0.03;
+
+// cells either side of the centre line of an exit corridor
+const CORRIDOR_HALF_WIDTH : i32 = 1;
+
+// where some WorldLink meets the zone this location is generated from
+#[derive(Debug,Copy,Clone,Serialize,Deserialize)]
+pub struct LinkEndpoint {
+    link_index: usize,
+    zone_coord: DPoint2,
+    land_link: bool,
+}
+
+impl LinkEndpoint {
+    pub fn new(link_index: usize, zone_coord: DPoint2, land_link: bool) -> LinkEndpoint {
+        LinkEndpoint {
+            link_index: link_index,
+            zone_coord: zone_coord,
+            land_link: land_link,
+        }
+    }
+}
+
+#[derive(Clone,Serialize,Deserialize)]
 pub struct LocationPrimitive {
     seed: u64,
     zone_in_world: Zone,
+    thresholds: MaterialThresholds,
+    endpoints: Vec<LinkEndpoint>,
 }
 
 impl LocationPrimitive {
-    pub fn new(seed: u64, zone_in_world: Zone) -> LocationPrimitive {
+    pub fn new(seed: u64, zone_in_world: Zone, thresholds: MaterialThresholds, endpoints: Vec<LinkEndpoint>) -> LocationPrimitive {
         LocationPrimitive {
             seed: seed,
             zone_in_world: zone_in_world,
+            thresholds: thresholds,
+            endpoints: endpoints,
+        }
+    }
+}
+
+impl Primitive<Location, LocationDiff> for LocationPrimitive {
+    fn generate_new(&self) -> Location {
+        Location::generate(self)
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Serialize,Deserialize)]
+pub enum LocationDiff {
+    PlaceObject(DPoint2, ObjectKind),
+    RemoveObject(DPoint2),
+    // eg: digging out a cell, or pouring water into it
+    SetMaterial(DPoint2, Material),
+}
+
+impl LocationDiff {
+    pub fn get_cell(&self) -> DPoint2 {
+        match self {
+            &LocationDiff::PlaceObject(cell, _) => cell,
+            &LocationDiff::RemoveObject(cell) => cell,
+            &LocationDiff::SetMaterial(cell, _) => cell,
         }
     }
 }
 
+// a traversable cell on the edge of a Location that leads along a WorldLink
+#[derive(Debug,Copy,Clone,PartialEq,Serialize,Deserialize)]
+pub struct LocationExit {
+    link_index: usize,
+    cell: DPoint2,
+    land_link: bool,
+}
+
+impl LocationExit {
+    pub fn get_link_index(&self) -> usize {self.link_index}
+    pub fn get_cell(&self) -> DPoint2 {self.cell}
+    pub fn is_land_link(&self) -> bool {self.land_link}
+
+    // can someone use this exit's corridor while standing on `mat`?
+    pub fn traversable_by(&self, mat: Material) -> bool {
+        if self.land_link {mat.is_land()} else {mat == Material::Water}
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub struct Location {
     materials : TotalGrid<Material>,
+    cell_data : TotalGrid<PointSampleData>,
+    exits : Vec<LocationExit>,
+    objects : Placements,
+}
+
+// 0.0 exactly ON a zone sample, growing towards 1.0 between them.
+// keeps the cells under each sample identical to what the world map shows
+fn detail_weight(sample_x: f32, sample_y: f32) -> f32 {
+    let edge = |v: f32| {
+        let f = v.fract();
+        4.0 * f * (1.0 - f)
+    };
+    1.0 - (1.0 - edge(sample_x)) * (1.0 - edge(sample_y))
 }
 
 impl Location {
-    pub fn generate(loc_prim: LocationPrimitive) -> Location {
+    pub fn generate(loc_prim: &LocationPrimitive) -> Location {
+        let zone = &loc_prim.zone_in_world;
+        // cell (0,0) sits on the top-left sample, the last cell on the bottom-right one,
+        // and every METERS_PER_ZONE_SAMPLE cells along lies the next sample
         let x_cells = METERS_PER_ZONE_SAMPLE
-            * loc_prim.zone_in_world.get_samples_per_row();
+            * (zone.get_samples_per_row() - 1) + 1;
         let y_cells = METERS_PER_ZONE_SAMPLE
-            * loc_prim.zone_in_world.get_samples_per_col();
+            * (zone.get_samples_per_col() - 1) + 1;
+        let dimensions = DPoint2::new(x_cells, y_cells);
+
+        let mut rng = Isaac64Rng::from_seed(&[loc_prim.seed]);
+        let height_detail = NoiseField::generate(&mut rng, [0.015, 0.2], 3);
+        let slope_detail = NoiseField::generate(&mut rng, [0.05, 0.4], 2);
+
+        let per_cell = 1.0 / METERS_PER_ZONE_SAMPLE as f32;
+        let mut which_data = |x, y| {
+            let (sample_x, sample_y) = (x as f32 * per_cell, y as f32 * per_cell);
+            let mut data = zone.interpolate_data(CPoint2::new(sample_x, sample_y));
+            let weight = detail_weight(sample_x, sample_y);
+            if weight > 0.0 {
+                let cell_pt = CPoint3::new(x as f32, y as f32, 0.0);
+                data.height += height_detail.sample_3d(cell_pt) * HEIGHT_DETAIL * weight;
+                data.slope = (data.slope + slope_detail.sample_3d(cell_pt) * SLOPE_DETAIL * weight).abs();
+            }
+            data
+        };
+        let cell_data = TotalGrid::new_from_func(dimensions, &mut which_data);
+
+        let thresholds = loc_prim.thresholds;
         let mut which_mat = |x, y| {
-            Material::Water
+            thresholds.material_for(cell_data.get(x, y))
+        };
+        let mut loc = Location {
+            materials: TotalGrid::new_from_func(dimensions, &mut which_mat),
+            cell_data: cell_data,
+            exits: vec![],
+            objects: Placements::new(),
         };
-        Location {
-            materials: TotalGrid::new_from_func(DPoint2::new(x_cells, y_cells), &mut which_mat),
+
+        let centre = DPoint2::new(x_cells / 2, y_cells / 2);
+        loc.exits = loc_prim.endpoints.iter().map(|endpoint| LocationExit {
+            link_index: endpoint.link_index,
+            cell: DPoint2::new(
+                endpoint.zone_coord.x * METERS_PER_ZONE_SAMPLE,
+                endpoint.zone_coord.y * METERS_PER_ZONE_SAMPLE,
+            ),
+            land_link: endpoint.land_link,
+        }).collect();
+        // corridors converge on the centre, so they overlap there. land corridors go
+        // first and keep their cells: a water corridor ends where it runs into one,
+        // which is where its travellers go ashore
+        let mut claimed = TotalGrid::new_from_func(dimensions, &mut |_, _| None);
+        let exits = loc.exits.clone();
+        for land in [true, false].iter() {
+            for exit in exits.iter().filter(|e| e.land_link == *land) {
+                loc.carve_corridor(exit, centre, &mut claimed);
+            }
+        }
+        // nothing may block an exit or its corridor
+        let keep_clear = claimed.map(|_, c| c.is_some());
+        loc.objects = Placements::generate(loc_prim.seed, &loc.materials, &loc.cell_data, &keep_clear);
+        loc
+    }
+
+    // forces a band of cells from the exit to `to` to be traversable the way the exit is.
+    // `claimed` has which cells corridors already took, and whether for a land link.
+    // a water corridor leaves land corridors alone, and stops on reaching one
+    fn carve_corridor(&mut self, exit: &LocationExit, to: DPoint2, claimed: &mut TotalGrid<Option<bool>>) {
+        let from = exit.cell;
+        let replacement = if exit.land_link {Material::Sand} else {Material::Water};
+        let steps = (to.x - from.x).abs().max((to.y - from.y).abs()).max(1);
+        for step in 0..(steps+1) {
+            let centre_x = from.x + (to.x - from.x) * step / steps;
+            let centre_y = from.y + (to.y - from.y) * step / steps;
+            if !exit.land_link && claimed.at(DPoint2::new(centre_x, centre_y)) == Some(&Some(true)) {
+                return
+            }
+            for y in (centre_y-CORRIDOR_HALF_WIDTH)..(centre_y+CORRIDOR_HALF_WIDTH+1) {
+                for x in (centre_x-CORRIDOR_HALF_WIDTH)..(centre_x+CORRIDOR_HALF_WIDTH+1) {
+                    let cell = DPoint2::new(x, y);
+                    let claim = match claimed.at_mut(cell) {
+                        Some(claim) => claim,
+                        None => continue,
+                    };
+                    if *claim == Some(true) && !exit.land_link {continue}
+                    *claim = Some(exit.land_link);
+                    let mat = &mut self.materials[cell];
+                    if !exit.traversable_by(*mat) {
+                        *mat = replacement;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn in_bounds(&self, cell: DPoint2) -> bool {
+        let dims = self.get_dimensions();
+        cell.x >= 0 && cell.y >= 0 && cell.x < dims.x && cell.y < dims.y
+    }
+
+    pub fn get_exits(&self) -> &[LocationExit] {&self.exits}
+
+    pub fn exit_for_link(&self, link_index: usize) -> Option<&LocationExit> {
+        self.exits.iter().find(|e| e.link_index == link_index)
+    }
+
+    pub fn get_dimensions(&self) -> DPoint2 {self.materials.get_dimensions()}
+
+    pub fn get_materials(&self) -> &TotalGrid<Material> {&self.materials}
+
+    pub fn get_objects(&self) -> &Placements {&self.objects}
+
+    // overwrite everything diffs can change, eg: from a snapshot
+    pub fn restore(&mut self, materials: TotalGrid<Material>, objects: Placements) {
+        assert_eq!(materials.get_dimensions(), self.get_dimensions());
+        self.materials = materials;
+        self.objects = objects;
+    }
+
+    pub fn material_at(&self, cell: DPoint2) -> Material {
+        *self.materials.get(cell.x as usize, cell.y as usize)
+    }
+
+    pub fn data_at(&self, cell: DPoint2) -> &PointSampleData {
+        self.cell_data.get(cell.x as usize, cell.y as usize)
+    }
+}
+
+impl AppliesDiff<LocationDiff> for Location {
+    // diffs outside the location are ignored
+    fn apply(&mut self, diff: &LocationDiff) {
+        if !self.in_bounds(diff.get_cell()) {return}
+        match diff {
+            &LocationDiff::PlaceObject(cell, kind) => {
+                self.objects.place(cell, kind);
+            },
+            &LocationDiff::RemoveObject(cell) => {
+                self.objects.remove(cell);
+            },
+            &LocationDiff::SetMaterial(cell, mat) => {
+                self.materials.put(cell.x as usize, cell.y as usize, mat);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{World,WorldPrimitive};
+    use super::super::grid::Neighbourhood;
+    use ::primitive::{Primitive,AppliesDiff};
+    use super::*;
+
+    fn worlds() -> Vec<World> {
+        (0..6).map(|seed| World::new(WorldPrimitive::new(seed, 0.5, 0.5).with_connected_zones())).collect()
+    }
+
+    // the cells carve_corridor from `exit` to `to` would carve, if nothing stopped it
+    fn corridor(exit: &LocationExit, to: DPoint2) -> Vec<DPoint2> {
+        let from = exit.cell;
+        let steps = (to.x - from.x).abs().max((to.y - from.y).abs()).max(1);
+        let mut cells = vec![];
+        for step in 0..(steps+1) {
+            let centre_x = from.x + (to.x - from.x) * step / steps;
+            let centre_y = from.y + (to.y - from.y) * step / steps;
+            for y in (centre_y-CORRIDOR_HALF_WIDTH)..(centre_y+CORRIDOR_HALF_WIDTH+1) {
+                for x in (centre_x-CORRIDOR_HALF_WIDTH)..(centre_x+CORRIDOR_HALF_WIDTH+1) {
+                    cells.push(DPoint2::new(x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn cells_under_samples_keep_their_material() {
+        let mut checked = 0;
+        for w in worlds() {
+            for (zone_index, zone) in w.get_zones().iter().enumerate() {
+                let loc = Location::generate(&w.location_primitive(zone_index, 7).unwrap());
+                let (per_row, per_col) = (zone.get_samples_per_row(), zone.get_samples_per_col());
+                assert_eq!(loc.get_dimensions(), DPoint2::new(
+                    METERS_PER_ZONE_SAMPLE * (per_row - 1) + 1,
+                    METERS_PER_ZONE_SAMPLE * (per_col - 1) + 1,
+                ));
+                let centre = DPoint2::new(loc.get_dimensions().x / 2, loc.get_dimensions().y / 2);
+                for y in 0..per_col {
+                    for x in 0..per_row {
+                        let cell = DPoint2::new(x * METERS_PER_ZONE_SAMPLE, y * METERS_PER_ZONE_SAMPLE);
+                        // exits may have carved through it
+                        if loc.get_exits().iter().any(|e| corridor(e, centre).contains(&cell)) {continue}
+                        assert_eq!(loc.material_at(cell), zone.get_sample(DPoint2::new(x, y)).get_mat(), "sample {:?}", (x, y));
+                        checked += 1;
+                    }
+                }
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn exits_lead_into_the_location() {
+        let (mut land, mut water) = (0, 0);
+        for w in worlds() {
+            for zone_index in 0..w.get_zones().len() {
+                let loc = Location::generate(&w.location_primitive(zone_index, 7).unwrap());
+                let mats = loc.get_materials();
+                let centre = DPoint2::new(loc.get_dimensions().x / 2, loc.get_dimensions().y / 2);
+                let ashore = mats.flood_fill(centre, Neighbourhood::Four, |m| m.is_land());
+                for e in loc.get_exits() {
+                    assert!(e.traversable_by(loc.material_at(e.get_cell())), "{:?}", e);
+                    for cell in corridor(e, centre) {
+                        assert!(loc.get_objects().object_at(cell).is_none(), "object on the corridor of {:?} at {:?}", e, cell);
+                    }
+                    if e.is_land_link() {
+                        land += 1;
+                        assert!(ashore[e.get_cell()], "land exit {:?} is cut off from the centre", e);
+                    } else {
+                        water += 1;
+                        // either sails all the way in, or lands somewhere connected to the centre
+                        let afloat = mats.flood_fill(e.get_cell(), Neighbourhood::Four, |m| *m == Material::Water);
+                        let lands = afloat.cell_iterator()
+                            .filter(|c| afloat[*c])
+                            .any(|c| mats.neighbours4(c).iter().any(|n| ashore[*n]));
+                        assert!(afloat[centre] || lands, "water exit {:?} leads nowhere", e);
+                    }
+                }
+            }
+        }
+        assert!(land > 0 && water > 0);
+    }
+
+    #[test]
+    fn diffs_apply_the_same_when_regenerating() {
+        let w = World::new(WorldPrimitive::new(0, 0.5, 0.5));
+        let prim = w.location_primitive(0, 7).unwrap();
+        let dims = prim.generate_new().get_dimensions();
+        let diffs = vec![
+            LocationDiff::PlaceObject(DPoint2::new(3, 3), ObjectKind::Ruin),
+            LocationDiff::SetMaterial(DPoint2::new(3, 4), Material::Water),
+            LocationDiff::RemoveObject(DPoint2::new(3, 3)),
+            LocationDiff::PlaceObject(DPoint2::new(dims.x - 1, dims.y - 1), ObjectKind::Boulder),
+            LocationDiff::SetMaterial(DPoint2::new(0, 0), Material::Rock),
+        ];
+        let mut by_hand = prim.generate_new();
+        for d in diffs.iter() {
+            by_hand.apply(d);
+        }
+        let diffed = prim.generate_diffed(&diffs);
+        assert!(diffed == by_hand);
+        assert_eq!(diffed.material_at(DPoint2::new(3, 4)), Material::Water);
+        assert_eq!(diffed.get_objects().object_at(DPoint2::new(3, 3)), None);
+        assert_eq!(diffed.get_objects().object_at(DPoint2::new(dims.x - 1, dims.y - 1)), Some(ObjectKind::Boulder));
+    }
+
+    #[test]
+    fn diffs_outside_the_location_are_ignored() {
+        let w = World::new(WorldPrimitive::new(0, 0.5, 0.5));
+        let prim = w.location_primitive(0, 7).unwrap();
+        let fresh = prim.generate_new();
+        let dims = fresh.get_dimensions();
+        let outside = [DPoint2::new(-1, 0), DPoint2::new(0, -1), DPoint2::new(dims.x, 0), DPoint2::new(0, dims.y)];
+        let mut diffs = vec![];
+        for cell in outside.iter() {
+            diffs.push(LocationDiff::PlaceObject(*cell, ObjectKind::Ruin));
+            diffs.push(LocationDiff::RemoveObject(*cell));
+            diffs.push(LocationDiff::SetMaterial(*cell, Material::Water));
         }
+        assert!(prim.generate_diffed(&diffs) == fresh);
     }
 }
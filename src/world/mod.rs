@@ -6,10 +6,19 @@ use super::{sigmoid,sig_0_pt5};
 
 pub mod zones;
 pub mod grid;
-mod location;
+pub mod grid_analysis;
+pub mod chunked_grid;
+pub mod location;
+pub mod placement;
+pub mod interior;
+pub mod pathfinding;
+pub mod zone_graph;
 use super::portals::UniquePoint;
-use self::location::LocationPrimitive;
+use super::primitive::{Primitive,AppliesDiff};
+use self::location::{LocationPrimitive,LinkEndpoint};
 use self::zones::{Zone,WorldLink};
+use self::zone_graph::{ZoneGraph,TravelMode};
+use self::grid::{TotalGrid,Topology};
 
 extern crate image;
 
@@ -19,7 +28,7 @@ const AZIMUTH_MULT: f32 = 1.0 - AZIMUTH_SHIFT;
 type FloatPixel = [f32 ; 3];
 type U8Pixel = [u8 ; 3];
 
-#[derive(Debug)]
+#[derive(Debug,Copy,Clone,PartialEq,Serialize,Deserialize)]
 pub struct PointSampleData {
     pub temp: f32,
     pub height: f32,
@@ -28,13 +37,27 @@ pub struct PointSampleData {
     pub slope: f32,
 }
 
-#[derive(PartialEq,Eq,Copy,Clone,Debug)]
+impl PointSampleData {
+    // linear interpolation. t=0.0 yields self, t=1.0 yields other
+    pub fn lerp(&self, other: &PointSampleData, t: f32) -> PointSampleData {
+        let mix = |a: f32, b: f32| a * (1.0 - t) + b * t;
+        PointSampleData {
+            temp: mix(self.temp, other.temp),
+            height: mix(self.height, other.height),
+            x_slope: mix(self.x_slope, other.x_slope),
+            y_slope: mix(self.y_slope, other.y_slope),
+            slope: mix(self.slope, other.slope),
+        }
+    }
+}
+
+#[derive(PartialEq,Eq,Copy,Clone,Debug,Hash,Serialize,Deserialize)]
 pub enum Material {
     Rock, Trees, Grass, Water, Ice, Snow, DarkRock, Sand,
 }
 
 impl Material {
-    fn is_land(self) -> bool {
+    pub fn is_land(self) -> bool {
         !(self == Material::Water)
     }
     fn col(&self) -> FloatPixel {
@@ -80,11 +103,40 @@ fn px_finalize(x: FloatPixel) -> U8Pixel {
     (px_bound(x[2]) * 254.0) as u8]
 }
 
-#[derive(Copy,Clone,Debug)]
+// everything needed to turn PointSampleData into a Material.
+// shared by the World map and the Locations generated from its zones
+#[derive(Copy,Clone,Debug,Serialize,Deserialize)]
+pub struct MaterialThresholds {
+    water_level: f32,
+    snow_below_temp: f32,
+}
+
+impl MaterialThresholds {
+    pub fn get_water_level(&self) -> f32 {self.water_level}
+    pub fn get_snow_below_temp(&self) -> f32 {self.snow_below_temp}
+
+    pub fn material_for(&self, point_data: &PointSampleData) -> Material {
+        let veg_dist = (((point_data.temp - 0.3).abs() + 0.01) * (point_data.slope*20.0 + point_data.height) - self.snow_below_temp).abs();
+        if point_data.height < self.water_level {
+            if point_data.temp + 0.02 < self.snow_below_temp {Material::Ice}
+            else {Material::Water}
+        }
+        else if point_data.temp < self.snow_below_temp {Material::Snow}
+        else if point_data.slope > 0.12 {Material::DarkRock}
+        else if veg_dist < 0.08*self.water_level && point_data.temp < 0.3 && point_data.slope > 0.01 {Material::Trees}
+        else if veg_dist < 0.12*self.water_level {Material::Grass}
+        else {Material::Rock}
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Serialize,Deserialize)]
 pub struct WorldPrimitive {
     super_seed: u64,
     distance_to_star: f32,
     star_energy: f32,
+    // add links until every zone can be reached from every other
+    #[serde(default)]
+    connected_zones: bool,
 }
 
 impl WorldPrimitive {
@@ -93,6 +145,42 @@ impl WorldPrimitive {
             super_seed: super_seed,
             distance_to_star: distance_to_star,
             star_energy: star_energy,
+            connected_zones: false,
+        }
+    }
+
+    pub fn with_connected_zones(mut self) -> WorldPrimitive {
+        self.connected_zones = true;
+        self
+    }
+
+    pub fn get_super_seed(&self) -> u64 {self.super_seed}
+    pub fn has_connected_zones(&self) -> bool {self.connected_zones}
+}
+
+impl Primitive<World, WorldDiff> for WorldPrimitive {
+    fn generate_new(&self) -> World {
+        World::new(*self)
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Serialize,Deserialize)]
+pub enum WorldDiff {
+    AddExitPoint(UniquePoint),
+    RemoveExitPoint(UniquePoint),
+}
+
+impl AppliesDiff<WorldDiff> for World {
+    fn apply(&mut self, diff: &WorldDiff) {
+        match diff {
+            &WorldDiff::AddExitPoint(pt) => {
+                if !self.exit_points.contains(&pt) {
+                    self.exit_points.push(pt);
+                }
+            },
+            &WorldDiff::RemoveExitPoint(pt) => {
+                self.exit_points.retain(|x| *x != pt);
+            },
         }
     }
 }
@@ -184,7 +272,12 @@ impl World {
             wp: wp,
         };
         w.zones = zones::generate_zones_for(&w, &mut rng);
-        w.links = zones::generate_links_for(&w.zones, &mut rng, &w);
+        let mut links = zones::generate_links_for(&w.zones, &mut rng, &w);
+        if wp.connected_zones {
+            // may have to give up. see is_connected
+            zones::connect_components(&w.zones, &mut links, &w);
+        }
+        w.links = links;
         w
     }
 
@@ -195,17 +288,61 @@ impl World {
         - sigmoid(self.size / (Self::pole_distance(pt.y) + 0.01), 1.0) * 0.3
     }
 
-    fn material_at(&self, pt: CPoint2, point_data: &PointSampleData) -> Material {
-        let veg_dist = (((point_data.temp - 0.3).abs() + 0.01) * (point_data.slope*20.0 + point_data.height) - self.snow_below_temp).abs();
-        if point_data.height < self.water_level {
-            if point_data.temp + 0.02 < self.snow_below_temp {Material::Ice}
-            else {Material::Water}
+    pub fn material_thresholds(&self) -> MaterialThresholds {
+        MaterialThresholds {
+            water_level: self.water_level,
+            snow_below_temp: self.snow_below_temp,
         }
-        else if point_data.temp < self.snow_below_temp {Material::Snow}
-        else if point_data.slope > 0.12 {Material::DarkRock}
-        else if veg_dist < 0.08*self.water_level && point_data.temp < 0.3 && point_data.slope > 0.01 {Material::Trees}
-        else if veg_dist < 0.12*self.water_level {Material::Grass}
-        else {Material::Rock}
+    }
+
+    fn material_at(&self, pt: CPoint2, point_data: &PointSampleData) -> Material {
+        self.material_thresholds().material_for(point_data)
+    }
+
+    // the whole planet sampled at the centre of each cell of a `dims` grid,
+    // which wraps around the planet as it does. `dims.x` should be even for the poles to meet
+    pub fn sample_grid(&self, dims: DPoint2) -> TotalGrid<PointSampleData> {
+        TotalGrid::new_from_func(dims, &mut |x, y| {
+            self.calc_sample_data_at(CPoint2::new(
+                (x as f32 + 0.5) / dims.x as f32,
+                (y as f32 + 0.5) / dims.y as f32,
+            ))
+        }).with_topology(Topology::Sphere)
+    }
+
+    pub fn material_grid(&self, dims: DPoint2) -> TotalGrid<Material> {
+        let thresholds = self.material_thresholds();
+        self.sample_grid(dims).map(|_, data| thresholds.material_for(data))
+    }
+
+    pub fn get_zones(&self) -> &[Zone] {&self.zones}
+
+    pub fn get_links(&self) -> &[WorldLink] {&self.links}
+
+    pub fn zone_graph(&self) -> ZoneGraph {
+        ZoneGraph::new(self.zones.len(), &self.links)
+    }
+
+    // whether every zone can be reached from every other. connected_zones makes this
+    // true unless connect_components ran out of places for links
+    pub fn is_connected(&self) -> bool {
+        self.zone_graph().is_connected(TravelMode::Any)
+    }
+
+    pub fn get_exit_points(&self) -> &[UniquePoint] {&self.exit_points}
+
+    pub fn get_primitive(&self) -> WorldPrimitive {self.wp}
+
+    pub fn location_primitive(&self, zone_index: usize, seed: u64) -> Option<LocationPrimitive> {
+        self.zones.get(zone_index).map(|zone| {
+            let endpoints = self.links.iter().enumerate()
+            .filter_map(|(link_index, l)| {
+                l.coord_in_zone(zone_index).map(|coord| {
+                    LinkEndpoint::new(link_index, coord, l.is_land_link())
+                })
+            }).collect();
+            LocationPrimitive::new(seed, zone.clone(), self.material_thresholds(), endpoints)
+        })
     }
 
     // (0.0, 1.0)
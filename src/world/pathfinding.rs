@@ -0,0 +1,435 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap,HashMap};
+use ::points::DPoint2;
+use super::Material;
+use super::grid::{TotalGrid,Neighbourhood};
+
+/*
+Paths over a Location's materials. Entering a cell costs that cell's material
+cost, times STRAIGHT for a straight step or DIAGONAL for a diagonal one.
+Leaving a cell is free, so whatever you start on never matters.
+Steps go across the edges of wrapping grids, as their topology says.
+
+For many agents heading to one target, a FlowField runs Dijkstra once
+outwards from the target. every agent then just follows `next_step`.
+*/
+
+const STRAIGHT: u32 = 10;
+const DIAGONAL: u32 = 14;
+
+// what entering a cell of each material costs. None means impassable
+#[derive(Debug,Clone,PartialEq)]
+pub struct MovementCosts {
+    costs: HashMap<Material, u32>,
+}
+
+impl MovementCosts {
+    // on foot. water can't be entered at all
+    pub fn walking() -> MovementCosts {
+        MovementCosts::none()
+        .with_cost(Material::Grass, Some(1))
+        .with_cost(Material::Sand, Some(1))
+        .with_cost(Material::Trees, Some(2))
+        .with_cost(Material::Rock, Some(2))
+        .with_cost(Material::Ice, Some(2))
+        .with_cost(Material::Snow, Some(3))
+        // steep
+        .with_cost(Material::DarkRock, Some(4))
+    }
+
+    // water is slow going, but possible
+    pub fn swimming() -> MovementCosts {
+        MovementCosts::walking().with_cost(Material::Water, Some(3))
+    }
+
+    // only water. eg: boats
+    pub fn swim_only() -> MovementCosts {
+        MovementCosts::none().with_cost(Material::Water, Some(1))
+    }
+
+    // everything impassable. start here to build your own
+    pub fn none() -> MovementCosts {
+        MovementCosts {costs: HashMap::new()}
+    }
+
+    pub fn with_cost(mut self, mat: Material, cost: Option<u32>) -> MovementCosts {
+        match cost {
+            Some(c) => {
+                assert!(c > 0);
+                self.costs.insert(mat, c);
+            },
+            None => {self.costs.remove(&mat);},
+        }
+        self
+    }
+
+    pub fn cost_of(&self, mat: Material) -> Option<u32> {
+        self.costs.get(&mat).cloned()
+    }
+
+    fn cheapest(&self) -> u32 {
+        self.costs.values().cloned().min().unwrap_or(1)
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Diagonals {
+    Never,
+    Always,
+    // a diagonal step is only allowed if both cells it squeezes between are passable
+    NoCornerCutting,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Path {
+    cells: Vec<DPoint2>,
+    cost: u32,
+}
+
+impl Path {
+    // from start to goal, both included
+    pub fn get_cells(&self) -> &[DPoint2] {&self.cells}
+    pub fn get_cost(&self) -> u32 {self.cost}
+    pub fn len(&self) -> usize {self.cells.len()}
+}
+
+// min-heap entry. ties go to the lower cell, so results never depend on push order
+#[derive(Copy,Clone,PartialEq,Eq)]
+struct Frontier {
+    priority: u32,
+    cell: DPoint2,
+}
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Frontier) -> Ordering {
+        other.priority.cmp(&self.priority)
+        .then_with(|| (other.cell.y, other.cell.x).cmp(&(self.cell.y, self.cell.x)))
+    }
+}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Frontier) -> Option<Ordering> {Some(self.cmp(other))}
+}
+
+fn material_at(materials: &TotalGrid<Material>, cell: DPoint2) -> Option<Material> {
+    materials.at(cell).cloned()
+}
+
+fn entry_cost(materials: &TotalGrid<Material>, costs: &MovementCosts, cell: DPoint2) -> Option<u32> {
+    material_at(materials, cell).and_then(|m| costs.cost_of(m))
+}
+
+// every step out of `cell`, with what it costs
+fn steps(materials: &TotalGrid<Material>, costs: &MovementCosts, diagonals: Diagonals, cell: DPoint2) -> Vec<(DPoint2, u32)> {
+    let mut v = vec![];
+    for &(dx, dy) in Neighbourhood::Eight.offsets().iter() {
+        let diagonal = dx != 0 && dy != 0;
+        if diagonal {
+            match diagonals {
+                Diagonals::Never => continue,
+                Diagonals::Always => (),
+                Diagonals::NoCornerCutting => {
+                    let passable = |pt| materials.wrap(pt).and_then(|pt| entry_cost(materials, costs, pt)).is_some();
+                    if !passable(cell.shift_x(dx)) || !passable(cell.shift_y(dy)) {continue}
+                },
+            }
+        }
+        let next = match materials.wrap(DPoint2::new(cell.x + dx, cell.y + dy)) {
+            Some(next) => next,
+            None => continue,
+        };
+        if let Some(c) = entry_cost(materials, costs, next) {
+            v.push((next, c * if diagonal {DIAGONAL} else {STRAIGHT}));
+        }
+    }
+    v
+}
+
+// never overestimates: every step costs at least the cheapest material,
+// and no way around the grid is shorter than the shortest separation
+fn heuristic(materials: &TotalGrid<Material>, diagonals: Diagonals, cheapest: u32, a: DPoint2, b: DPoint2) -> u32 {
+    let topology = materials.get_topology();
+    topology.separations(materials.get_dimensions(), a, b).into_iter()
+    .map(|(dx, dy)| {
+        let (lo, hi) = if dx < dy {(dx, dy)} else {(dy, dx)};
+        cheapest * match diagonals {
+            Diagonals::Never => STRAIGHT * (dx + dy),
+            _ => DIAGONAL * lo + STRAIGHT * (hi - lo),
+        }
+    })
+    .min()
+    .unwrap_or(0)
+}
+
+// A*. None if `to` can't be reached (or either end is outside the grid)
+pub fn find_path(materials: &TotalGrid<Material>, costs: &MovementCosts, diagonals: Diagonals, from: DPoint2, to: DPoint2) -> Option<Path> {
+    if material_at(materials, from).is_none() || entry_cost(materials, costs, to).is_none() {
+        return if from == to && material_at(materials, from).is_some() {
+            Some(Path {cells: vec![from], cost: 0})
+        } else {
+            None
+        }
+    }
+    let cheapest = costs.cheapest();
+    let mut best: HashMap<DPoint2, u32> = HashMap::new();
+    let mut came_from: HashMap<DPoint2, DPoint2> = HashMap::new();
+    let mut open = BinaryHeap::new();
+    best.insert(from, 0);
+    open.push(Frontier {priority: heuristic(materials, diagonals, cheapest, from, to), cell: from});
+
+    while let Some(Frontier {priority, cell}) = open.pop() {
+        let so_far = best[&cell];
+        // a stale entry, this cell was reached more cheaply since
+        if priority > so_far + heuristic(materials, diagonals, cheapest, cell, to) {continue}
+        if cell == to {
+            let mut cells = vec![to];
+            let mut at = to;
+            while let Some(prev) = came_from.get(&at) {
+                cells.push(*prev);
+                at = *prev;
+            }
+            cells.reverse();
+            return Some(Path {cells: cells, cost: so_far});
+        }
+        for (next, step) in steps(materials, costs, diagonals, cell) {
+            let cost = so_far + step;
+            if best.get(&next).map(|b| cost < *b).unwrap_or(true) {
+                best.insert(next, cost);
+                came_from.insert(next, cell);
+                open.push(Frontier {priority: cost + heuristic(materials, diagonals, cheapest, next, to), cell: next});
+            }
+        }
+    }
+    None
+}
+
+// the cost to `target` from every cell that can reach it. like find_path, cells that
+// can't be entered can still be left, so they get a cost too
+#[derive(Debug,Clone)]
+pub struct FlowField {
+    target: DPoint2,
+    diagonals: Diagonals,
+    costs: MovementCosts,
+    remaining: TotalGrid<Option<u32>>,
+}
+
+impl FlowField {
+    pub fn towards(materials: &TotalGrid<Material>, costs: &MovementCosts, diagonals: Diagonals, target: DPoint2) -> FlowField {
+        let mut remaining = TotalGrid::new_from_func(materials.get_dimensions(), &mut |_, _| None);
+        let mut open = BinaryHeap::new();
+        if entry_cost(materials, costs, target).is_some() {
+            remaining.put(target.x as usize, target.y as usize, Some(0));
+            open.push(Frontier {priority: 0, cell: target});
+        }
+        while let Some(Frontier {priority, cell}) = open.pop() {
+            if *remaining.get(cell.x as usize, cell.y as usize) != Some(priority) {continue}
+            // steps are symmetric, so the passable cells that can step into `cell` are
+            // exactly those it can step to, and each the same kind of step back.
+            // (whether it was diagonal can't be told from the cells, as it may have wrapped)
+            let into = entry_cost(materials, costs, cell).unwrap();
+            for (prev, step) in steps(materials, costs, diagonals, cell) {
+                let kind = step / entry_cost(materials, costs, prev).unwrap();
+                let cost = priority + into * kind;
+                let slot = remaining.get_mut(prev.x as usize, prev.y as usize);
+                if slot.map(|r| cost < r).unwrap_or(true) {
+                    *slot = Some(cost);
+                    open.push(Frontier {priority: cost, cell: prev});
+                }
+            }
+        }
+        // one step out onto the passable cells around them. nothing ever steps into these,
+        // so they can't lower each other's costs
+        let impassable: Vec<DPoint2> = remaining.cell_iterator()
+            .filter(|c| *c != target && entry_cost(materials, costs, *c).is_none())
+            .collect();
+        for cell in impassable {
+            let cost = steps(materials, costs, diagonals, cell).into_iter()
+                .filter_map(|(next, step)| remaining[next].map(|r| r + step))
+                .min();
+            remaining[cell] = cost;
+        }
+        // standing on the target costs nothing, even if it can't be entered
+        if let Some(r) = remaining.at_mut(target) {
+            *r = Some(0);
+        }
+        FlowField {
+            target: target,
+            diagonals: diagonals,
+            costs: costs.clone(),
+            remaining: remaining,
+        }
+    }
+
+    pub fn get_target(&self) -> DPoint2 {self.target}
+
+    // None if the target can't be reached from `cell`
+    pub fn cost_from(&self, cell: DPoint2) -> Option<u32> {
+        self.remaining.at(cell).cloned().and_then(|r| r)
+    }
+
+    // where to go next from `cell` on a cheapest path. None at the target, or if it's unreachable
+    pub fn next_step(&self, materials: &TotalGrid<Material>, cell: DPoint2) -> Option<DPoint2> {
+        let here = match self.cost_from(cell) {
+            Some(r) => r,
+            None => return None,
+        };
+        steps(materials, &self.costs, self.diagonals, cell).into_iter()
+        .filter_map(|(next, step)| self.cost_from(next).map(|r| (next, step + r)))
+        .filter(|&(_, total)| total == here)
+        .map(|(next, _)| next)
+        .next()
+    }
+}
+
+// flow fields by target, all for the same costs and diagonals.
+// must be cleared whenever the materials they were built from change
+#[derive(Debug,Clone)]
+pub struct FlowFieldCache {
+    costs: MovementCosts,
+    diagonals: Diagonals,
+    fields: HashMap<DPoint2, FlowField>,
+}
+
+impl FlowFieldCache {
+    pub fn new(costs: MovementCosts, diagonals: Diagonals) -> FlowFieldCache {
+        FlowFieldCache {
+            costs: costs,
+            diagonals: diagonals,
+            fields: HashMap::new(),
+        }
+    }
+
+    // builds the field on the first request for `target`
+    pub fn get(&mut self, materials: &TotalGrid<Material>, target: DPoint2) -> &FlowField {
+        let costs = &self.costs;
+        let diagonals = self.diagonals;
+        self.fields.entry(target)
+        .or_insert_with(|| FlowField::towards(materials, costs, diagonals, target))
+    }
+
+    pub fn is_cached(&self, target: DPoint2) -> bool {self.fields.contains_key(&target)}
+
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::rand::{SeedableRng,Rng,Isaac64Rng};
+    use super::super::grid::Topology;
+
+    const TOPOLOGIES: [Topology; 4] = [Topology::Bounded, Topology::WrapX, Topology::Torus, Topology::Sphere];
+    const DIAGONALS: [Diagonals; 3] = [Diagonals::Never, Diagonals::Always, Diagonals::NoCornerCutting];
+
+    // small grids of mixed materials, a quarter of them water
+    fn random_grids(seed: u64, topology: Topology) -> Vec<TotalGrid<Material>> {
+        let mut rng = Isaac64Rng::from_seed(&[seed]);
+        let land = [Material::Grass, Material::Sand, Material::Trees, Material::Rock, Material::Snow, Material::DarkRock];
+        (0..12).map(|_| {
+            let dims = DPoint2::new(rng.gen_range(1, 10), rng.gen_range(1, 10));
+            TotalGrid::new_from_func(dims, &mut |_, _| {
+                if rng.gen_weighted_bool(4) {Material::Water} else {*rng.choose(&land).unwrap()}
+            }).with_topology(topology)
+        }).collect()
+    }
+
+    // what a single step from `a` to its neighbour `b` costs, checked against the rules
+    // for diagonals rather than through `steps`
+    fn step_cost(materials: &TotalGrid<Material>, costs: &MovementCosts, diagonals: Diagonals, a: DPoint2, b: DPoint2) -> Option<u32> {
+        let passable = |pt: DPoint2| materials.wrap(pt).and_then(|pt| materials.at(pt)).and_then(|m| costs.cost_of(*m)).is_some();
+        let into = costs.cost_of(*materials.at(b).unwrap()).unwrap();
+        Neighbourhood::Eight.offsets().iter()
+        .filter(|&&(dx, dy)| materials.wrap(DPoint2::new(a.x + dx, a.y + dy)) == Some(b))
+        .filter_map(|&(dx, dy)| {
+            if dx == 0 || dy == 0 {
+                return Some(into * STRAIGHT);
+            }
+            match diagonals {
+                Diagonals::Never => None,
+                Diagonals::Always => Some(into * DIAGONAL),
+                Diagonals::NoCornerCutting => {
+                    if passable(a.shift_x(dx)) && passable(a.shift_y(dy)) {Some(into * DIAGONAL)} else {None}
+                },
+            }
+        })
+        .min()
+    }
+
+    #[test]
+    fn flow_fields_agree_with_a_star() {
+        let costs = MovementCosts::walking();
+        for (t, topology) in TOPOLOGIES.iter().enumerate() {
+            for (g, materials) in random_grids(t as u64, *topology).into_iter().enumerate() {
+                let dims = materials.get_dimensions();
+                let mut rng = Isaac64Rng::from_seed(&[t as u64, g as u64]);
+                let target = DPoint2::new(rng.gen_range(0, dims.x), rng.gen_range(0, dims.y));
+                for diagonals in DIAGONALS.iter() {
+                    let field = FlowField::towards(&materials, &costs, *diagonals, target);
+                    // impassable starts included: you can always leave the cell you are on
+                    for from in materials.cell_iterator() {
+                        let path = find_path(&materials, &costs, *diagonals, from, target);
+                        assert_eq!(path.as_ref().map(|p| p.get_cost()), field.cost_from(from),
+                            "{:?} {:?} from {:?} to {:?}", topology, diagonals, from, target);
+                        if let Some(path) = path {
+                            assert_eq!(path.get_cells().first(), Some(&from));
+                            assert_eq!(path.get_cells().last(), Some(&target));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn following_the_field_costs_what_it_says() {
+        let costs = MovementCosts::walking();
+        for (t, topology) in TOPOLOGIES.iter().enumerate() {
+            for (g, materials) in random_grids(10 + t as u64, *topology).into_iter().enumerate() {
+                let dims = materials.get_dimensions();
+                let mut rng = Isaac64Rng::from_seed(&[t as u64, g as u64]);
+                let target = DPoint2::new(rng.gen_range(0, dims.x), rng.gen_range(0, dims.y));
+                for diagonals in DIAGONALS.iter() {
+                    let field = FlowField::towards(&materials, &costs, *diagonals, target);
+                    for from in materials.cell_iterator() {
+                        let mut spent = 0;
+                        let mut at = from;
+                        while let Some(next) = field.next_step(&materials, at) {
+                            // never cuts a corner it isn't allowed to, nor steps diagonally under Never
+                            spent += step_cost(&materials, &costs, *diagonals, at, next)
+                                .expect("next_step took a step that isn't allowed");
+                            at = next;
+                        }
+                        match field.cost_from(from) {
+                            Some(cost) => {
+                                assert_eq!(at, target);
+                                assert_eq!(spent, cost);
+                            },
+                            None => assert_eq!(at, from),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_corner_cutting_goes_around() {
+        // the diagonal from (0,0) to (1,1) squeezes between two water cells
+        let materials = TotalGrid::new_from_func(DPoint2::new(3, 3), &mut |x, y| {
+            if (x, y) == (1, 0) || (x, y) == (0, 1) {Material::Water} else {Material::Grass}
+        });
+        let costs = MovementCosts::walking();
+        let (from, to) = (DPoint2::new(0, 0), DPoint2::new(1, 1));
+        assert_eq!(find_path(&materials, &costs, Diagonals::Always, from, to).map(|p| p.get_cost()), Some(DIAGONAL));
+        assert_eq!(find_path(&materials, &costs, Diagonals::NoCornerCutting, from, to), None);
+        assert_eq!(find_path(&materials, &costs, Diagonals::Never, from, to), None);
+        assert_eq!(FlowField::towards(&materials, &costs, Diagonals::NoCornerCutting, to).cost_from(from), None);
+        // from (2,0) only one side of the diagonal is open, so it takes two straight steps
+        let from = DPoint2::new(2, 0);
+        let path = find_path(&materials, &costs, Diagonals::NoCornerCutting, from, to).unwrap();
+        assert_eq!(path.get_cells(), &[from, DPoint2::new(2, 1), to]);
+        assert_eq!(path.get_cost(), 2 * STRAIGHT);
+    }
+}
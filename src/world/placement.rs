@@ -0,0 +1,281 @@
+use super::{Material,PointSampleData};
+use super::grid::TotalGrid;
+use ::points::*;
+use ::procedural::poisson_disk;
+use ::rand::{SeedableRng,Rng,Isaac64Rng};
+use std::collections::HashMap;
+use ::serde::{Serialize,Serializer,Deserialize,Deserializer};
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,Serialize,Deserialize)]
+pub enum ObjectKind {
+    Tree, Boulder, Ruin,
+}
+
+// many objects of the same kind, stored together
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct ObjectBatch {
+    kind: ObjectKind,
+    positions: Vec<DPoint2>,
+}
+
+impl ObjectBatch {
+    pub fn get_kind(&self) -> ObjectKind {self.kind}
+    pub fn get_positions(&self) -> &[DPoint2] {&self.positions}
+}
+
+// a one-off object that is worth tracking on its own
+#[derive(Debug,Copy,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Object {
+    position: DPoint2,
+    kind: ObjectKind,
+}
+
+impl Object {
+    pub fn get_position(&self) -> DPoint2 {self.position}
+    pub fn get_kind(&self) -> ObjectKind {self.kind}
+}
+
+struct PlacementRule {
+    kind: ObjectKind,
+    min_spacing: f32,
+    batched: bool,
+    // chance that a poisson-disk candidate on this cell is kept
+    chance: fn(Material, &PointSampleData) -> f32,
+}
+
+fn tree_chance(mat: Material, data: &PointSampleData) -> f32 {
+    match mat {
+        Material::Trees => 0.9,
+        Material::Grass => (0.08 - data.slope * 0.5).max(0.0),
+        _ => 0.0,
+    }
+}
+
+fn boulder_chance(mat: Material, data: &PointSampleData) -> f32 {
+    match mat {
+        Material::Rock => 0.2 + data.slope,
+        Material::DarkRock => 0.4 + data.slope,
+        Material::Snow => 0.05,
+        _ => 0.0,
+    }
+}
+
+fn ruin_chance(mat: Material, data: &PointSampleData) -> f32 {
+    match mat {
+        Material::Grass | Material::Rock if data.slope < 0.03 => 0.15,
+        _ => 0.0,
+    }
+}
+
+// order matters: earlier rules claim cells first
+const RULES : [PlacementRule ; 3] = [
+    PlacementRule {kind: ObjectKind::Ruin, min_spacing: 40.0, batched: false, chance: ruin_chance},
+    PlacementRule {kind: ObjectKind::Boulder, min_spacing: 4.0, batched: true, chance: boulder_chance},
+    PlacementRule {kind: ObjectKind::Tree, min_spacing: 2.5, batched: true, chance: tree_chance},
+];
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Placements {
+    batches: Vec<ObjectBatch>,
+    #[serde(with = "individuals_as_list")]
+    individuals: HashMap<DPoint2, Object>,
+}
+
+// json can't key a map with DPoint2. objects know their own position anyway
+mod individuals_as_list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(individuals: &HashMap<DPoint2, Object>, s: S) -> Result<S::Ok, S::Error> {
+        let mut list: Vec<&Object> = individuals.values().collect();
+        list.sort_by_key(|o| (o.position.y, o.position.x));
+        list.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<HashMap<DPoint2, Object>, D::Error> {
+        let list: Vec<Object> = Vec::deserialize(d)?;
+        Ok(list.into_iter().map(|o| (o.position, o)).collect())
+    }
+}
+
+impl Placements {
+    pub fn new() -> Placements {
+        Placements {
+            batches: vec![],
+            individuals: HashMap::new(),
+        }
+    }
+
+    // scatters objects over the given cells, except those marked in `keep_clear`.
+    // the same seed and grids always give the same result
+    pub fn generate(seed: u64, materials: &TotalGrid<Material>, cell_data: &TotalGrid<PointSampleData>, keep_clear: &TotalGrid<bool>) -> Placements {
+        let mut placements = Placements::new();
+        let (width, height) = (materials.get_width(), materials.get_height());
+        let mut occupied = keep_clear.clone();
+        for (rule_index, rule) in RULES.iter().enumerate() {
+            let mut rng = Isaac64Rng::from_seed(&[seed, rule_index as u64 + 1]);
+            let mut batch = ObjectBatch {kind: rule.kind, positions: vec![]};
+            for pt in poisson_disk(&mut rng, width as f32, height as f32, rule.min_spacing) {
+                let (x, y) = (pt.x as usize, pt.y as usize);
+                if *occupied.get(x, y) {continue}
+                let chance = (rule.chance)(*materials.get(x, y), cell_data.get(x, y));
+                if rng.gen::<f32>() >= chance {continue}
+                occupied.put(x, y, true);
+                let position = DPoint2::new(x as i32, y as i32);
+                if rule.batched {
+                    batch.positions.push(position);
+                } else {
+                    placements.individuals.insert(position, Object {position: position, kind: rule.kind});
+                }
+            }
+            if !batch.positions.is_empty() {
+                placements.batches.push(batch);
+            }
+        }
+        placements
+    }
+
+    pub fn get_batches(&self) -> &[ObjectBatch] {&self.batches}
+
+    pub fn get_individuals(&self) -> &HashMap<DPoint2, Object> {&self.individuals}
+
+    pub fn object_at(&self, cell: DPoint2) -> Option<ObjectKind> {
+        if let Some(o) = self.individuals.get(&cell) {
+            return Some(o.kind)
+        }
+        self.batches.iter()
+        .find(|b| b.positions.contains(&cell))
+        .map(|b| b.kind)
+    }
+
+    // objects placed after generation are always tracked individually.
+    // returns false if the cell was already taken
+    pub fn place(&mut self, cell: DPoint2, kind: ObjectKind) -> bool {
+        if self.object_at(cell).is_some() {
+            return false
+        }
+        self.individuals.insert(cell, Object {position: cell, kind: kind});
+        true
+    }
+
+    pub fn remove(&mut self, cell: DPoint2) -> Option<ObjectKind> {
+        if let Some(o) = self.individuals.remove(&cell) {
+            return Some(o.kind)
+        }
+        for batch in self.batches.iter_mut() {
+            if let Some(i) = batch.positions.iter().position(|p| *p == cell) {
+                batch.positions.remove(i);
+                return Some(batch.kind)
+            }
+        }
+        None
+    }
+
+    pub fn count(&self) -> usize {
+        self.individuals.len()
+        + self.batches.iter().map(|b| b.positions.len()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::serde_json;
+    use super::*;
+
+    const SIDE: i32 = 60;
+
+    // trees to the left, rock in the middle, grass to the right. all flat
+    fn grids() -> (TotalGrid<Material>, TotalGrid<PointSampleData>) {
+        let dims = DPoint2::new(SIDE, SIDE);
+        let materials = TotalGrid::new_from_func(dims, &mut |x, _| {
+            if x < 20 {Material::Trees} else if x < 40 {Material::Rock} else {Material::Grass}
+        });
+        let flat = PointSampleData {temp: 0.5, height: 0.5, x_slope: 0.0, y_slope: 0.0, slope: 0.0};
+        (materials, TotalGrid::new_filled(dims, flat))
+    }
+
+    fn nothing_clear() -> TotalGrid<bool> {
+        TotalGrid::new_filled(DPoint2::new(SIDE, SIDE), false)
+    }
+
+    fn all_positions(p: &Placements) -> Vec<(DPoint2, ObjectKind)> {
+        let mut v: Vec<(DPoint2, ObjectKind)> = p.get_individuals().values().map(|o| (o.position, o.kind)).collect();
+        for b in p.get_batches() {
+            v.extend(b.positions.iter().map(|pt| (*pt, b.kind)));
+        }
+        v.sort_by_key(|&(pt, _)| (pt.y, pt.x));
+        v
+    }
+
+    #[test]
+    fn same_seed_same_objects() {
+        let (materials, data) = grids();
+        let a = Placements::generate(3, &materials, &data, &nothing_clear());
+        let b = Placements::generate(3, &materials, &data, &nothing_clear());
+        let c = Placements::generate(4, &materials, &data, &nothing_clear());
+        assert_eq!(a, b);
+        assert!(a.count() > 0);
+        assert!(all_positions(&a) != all_positions(&c));
+        // each rule's objects come from its own poisson disk, seeded by the seed and rule index
+        for (rule_index, rule) in RULES.iter().enumerate() {
+            let mut rng = Isaac64Rng::from_seed(&[3, rule_index as u64 + 1]);
+            let candidates: Vec<DPoint2> = poisson_disk(&mut rng, SIDE as f32, SIDE as f32, rule.min_spacing).iter()
+                .map(|pt| DPoint2::new(pt.x as i32, pt.y as i32))
+                .collect();
+            for (pt, kind) in all_positions(&a) {
+                if kind == rule.kind {
+                    assert!(candidates.contains(&pt), "{:?} {:?}", kind, pt);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn poisson_disk_keeps_its_distance() {
+        let mut rng = Isaac64Rng::from_seed(&[28]);
+        for &min_spacing in [1.5, 2.5, 4.0, 17.0].iter() {
+            let points = poisson_disk(&mut rng, 50.0, 30.0, min_spacing);
+            assert!(points.len() > 1);
+            for (i, a) in points.iter().enumerate() {
+                assert!(a.x >= 0.0 && a.y >= 0.0 && a.x < 50.0 && a.y < 30.0);
+                for b in points[i + 1..].iter() {
+                    let (dx, dy) = (a.x - b.x, a.y - b.y);
+                    assert!((dx * dx + dy * dy).sqrt() >= min_spacing, "{:?} and {:?}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keep_clear_stays_clear() {
+        let (materials, data) = grids();
+        // a cross through the middle, and the whole bottom row
+        let keep_clear = TotalGrid::new_from_func(DPoint2::new(SIDE, SIDE), &mut |x, y| {
+            (x >= 28 && x < 32) || (y >= 28 && y < 32) || y == SIDE as usize - 1
+        });
+        for seed in 0..10 {
+            let p = Placements::generate(seed, &materials, &data, &keep_clear);
+            assert!(p.count() > 0);
+            for (pt, kind) in all_positions(&p) {
+                assert!(!keep_clear[pt], "{:?} on {:?}", kind, pt);
+            }
+        }
+    }
+
+    #[test]
+    fn individuals_round_trip_as_a_list() {
+        let (materials, data) = grids();
+        let mut p = Placements::generate(5, &materials, &data, &nothing_clear());
+        // ruins are rare, so add a few more
+        let free: Vec<DPoint2> = (0..SIDE).map(|i| DPoint2::new(i, SIDE - 1 - i)).filter(|pt| p.object_at(*pt).is_none()).collect();
+        for pt in free.iter().take(3) {
+            assert!(p.place(*pt, ObjectKind::Ruin));
+        }
+        assert!(p.get_individuals().len() >= 3);
+        let json = serde_json::to_string(&p).unwrap();
+        assert!(json.contains("\"individuals\":["));
+        let back: Placements = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, p);
+        // and the list is in a fixed order, so saves don't churn
+        assert_eq!(serde_json::to_string(&back).unwrap(), json);
+    }
+}
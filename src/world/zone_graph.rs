@@ -0,0 +1,236 @@
+use super::pt_wider_dist;
+use super::zones::WorldLink;
+use ::points::CPoint2;
+
+/*
+Zones are the nodes, WorldLinks the edges.
+
+A route's distance is what a traveller actually covers: every link's length,
+plus the way across each zone in between, from where one link arrives to where
+the next one leaves. crossing the first and last zone is not counted, as the
+traveller may start and end anywhere in them.
+*/
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum TravelMode {
+    Any,
+    LandOnly,
+    SeaOnly,
+}
+
+impl TravelMode {
+    fn allows(self, link: &WorldLink) -> bool {
+        match self {
+            TravelMode::Any => true,
+            TravelMode::LandOnly => link.is_land_link(),
+            TravelMode::SeaOnly => !link.is_land_link(),
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Route {
+    zones: Vec<usize>,
+    links: Vec<usize>,
+    distance: f32,
+}
+
+impl Route {
+    // from start to goal, both included
+    pub fn get_zones(&self) -> &[usize] {&self.zones}
+    // indices into the world's links. links[i] joins zones[i] and zones[i+1]
+    pub fn get_links(&self) -> &[usize] {&self.links}
+    pub fn get_distance(&self) -> f32 {self.distance}
+}
+
+#[derive(Debug,Clone)]
+pub struct ZoneGraph {
+    zone_count: usize,
+    links: Vec<WorldLink>,
+    // for each zone: (link index, zone on the other end)
+    adjacent: Vec<Vec<(usize, usize)>>,
+}
+
+// where `link` touches `zone`, in world coordinates
+fn end_in(link: &WorldLink, zone: usize) -> CPoint2 {
+    if link.get_zone_a() == zone {link.get_world_a_pt()} else {link.get_world_b_pt()}
+}
+
+impl ZoneGraph {
+    pub fn new(zone_count: usize, links: &[WorldLink]) -> ZoneGraph {
+        let mut adjacent: Vec<Vec<(usize, usize)>> = (0..zone_count).map(|_| vec![]).collect();
+        for (i, l) in links.iter().enumerate() {
+            adjacent[l.get_zone_a()].push((i, l.get_zone_b()));
+            adjacent[l.get_zone_b()].push((i, l.get_zone_a()));
+        }
+        ZoneGraph {
+            zone_count: zone_count,
+            links: links.to_vec(),
+            adjacent: adjacent,
+        }
+    }
+
+    pub fn zone_count(&self) -> usize {self.zone_count}
+
+    // (link index, zone on the other end) for every link of the zone
+    pub fn neighbours(&self, zone: usize) -> &[(usize, usize)] {&self.adjacent[zone]}
+
+    // Dijkstra over (link, direction) pairs, since the cost of crossing a zone
+    // depends on which link you came in by. None if there is no such route
+    pub fn shortest_route(&self, from: usize, to: usize, mode: TravelMode) -> Option<Route> {
+        if from >= self.zone_count || to >= self.zone_count {return None}
+        if from == to {
+            return Some(Route {zones: vec![from], links: vec![], distance: 0.0});
+        }
+        // state 2*link + 0 arrives in the link's zone b, 2*link + 1 in zone a
+        let arrives_in = |state: usize| {
+            let l = &self.links[state / 2];
+            if state % 2 == 0 {l.get_zone_b()} else {l.get_zone_a()}
+        };
+        let state_for = |link: usize, into: usize| 2 * link + if self.links[link].get_zone_b() == into {0} else {1};
+        let states = self.links.len() * 2;
+        let mut dist: Vec<Option<f32>> = vec![None; states];
+        let mut prev: Vec<Option<usize>> = vec![None; states];
+        let mut done = vec![false; states];
+        for &(link, other) in self.adjacent[from].iter() {
+            if mode.allows(&self.links[link]) {
+                dist[state_for(link, other)] = Some(self.links[link].length());
+            }
+        }
+        loop {
+            // few enough links that a linear scan beats a heap
+            let next = (0..states)
+                .filter(|s| !done[*s])
+                .filter_map(|s| dist[s].map(|d| (s, d)))
+                .fold(None, |best: Option<(usize, f32)>, (s, d)| match best {
+                    Some((_, bd)) if bd <= d => best,
+                    _ => Some((s, d)),
+                });
+            let (state, d) = match next {
+                Some(x) => x,
+                None => return None,
+            };
+            done[state] = true;
+            let zone = arrives_in(state);
+            if zone == to {
+                let mut links = vec![];
+                let mut at = Some(state);
+                while let Some(s) = at {
+                    links.push(s / 2);
+                    at = prev[s];
+                }
+                links.reverse();
+                let mut zones = vec![from];
+                let mut z = from;
+                for l in links.iter() {
+                    let link = &self.links[*l];
+                    z = if link.get_zone_a() == z {link.get_zone_b()} else {link.get_zone_a()};
+                    zones.push(z);
+                }
+                return Some(Route {zones: zones, links: links, distance: d});
+            }
+            let arrived_at = end_in(&self.links[state / 2], zone);
+            for &(link, other) in self.adjacent[zone].iter() {
+                if link == state / 2 || !mode.allows(&self.links[link]) {continue}
+                let next_state = state_for(link, other);
+                let l = &self.links[link];
+                let cost = d + pt_wider_dist(arrived_at, end_in(l, zone)) + l.length();
+                if dist[next_state].map(|old| cost < old).unwrap_or(true) {
+                    dist[next_state] = Some(cost);
+                    prev[next_state] = Some(state);
+                }
+            }
+        }
+    }
+
+    // each component sorted, and the components sorted by their lowest zone
+    pub fn components(&self, mode: TravelMode) -> Vec<Vec<usize>> {
+        let mut component_of: Vec<Option<usize>> = vec![None; self.zone_count];
+        let mut components = vec![];
+        for start in 0..self.zone_count {
+            if component_of[start].is_some() {continue}
+            let id = components.len();
+            let mut members = vec![start];
+            component_of[start] = Some(id);
+            let mut i = 0;
+            while i < members.len() {
+                let z = members[i];
+                for &(link, other) in self.adjacent[z].iter() {
+                    if mode.allows(&self.links[link]) && component_of[other].is_none() {
+                        component_of[other] = Some(id);
+                        members.push(other);
+                    }
+                }
+                i += 1;
+            }
+            members.sort();
+            components.push(members);
+        }
+        components
+    }
+
+    pub fn is_connected(&self, mode: TravelMode) -> bool {
+        self.components(mode).len() <= 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(x: f32, y: f32) -> CPoint2 {
+        CPoint2::new(x, y)
+    }
+
+    // 0 -land- 1 -land- 2 -sea- 3, with a shorter sea link straight from 0 to 2
+    fn graph() -> ZoneGraph {
+        ZoneGraph::new(4, &[
+            WorldLink::by_hand(0, pt(0.10, 0.5), 1, pt(0.20, 0.5), true),
+            WorldLink::by_hand(1, pt(0.20, 0.5), 2, pt(0.30, 0.5), true),
+            WorldLink::by_hand(0, pt(0.12, 0.5), 2, pt(0.28, 0.5), false),
+            WorldLink::by_hand(2, pt(0.30, 0.6), 3, pt(0.40, 0.6), false),
+        ])
+    }
+
+    fn assert_route(route: Option<Route>, zones: &[usize], links: &[usize], distance: f32) {
+        let route = route.expect("expected a route");
+        assert_eq!(route.get_zones(), zones);
+        assert_eq!(route.get_links(), links);
+        assert!((route.get_distance() - distance).abs() < 1e-5, "{} != {}", route.get_distance(), distance);
+    }
+
+    #[test]
+    fn routes_for_each_travel_mode() {
+        let g = graph();
+        let len = |i: usize| g.links[i].length();
+        // crossing zone 2, from where the sea link arrives to where the next one leaves
+        let across_2 = pt_wider_dist(pt(0.28, 0.5), pt(0.30, 0.6));
+
+        assert_route(g.shortest_route(0, 2, TravelMode::Any), &[0, 2], &[2], len(2));
+        assert_route(g.shortest_route(0, 2, TravelMode::SeaOnly), &[0, 2], &[2], len(2));
+        // the land route can't take the shortcut
+        assert_route(g.shortest_route(0, 2, TravelMode::LandOnly), &[0, 1, 2], &[0, 1], len(0) + len(1));
+        assert_route(g.shortest_route(2, 0, TravelMode::LandOnly), &[2, 1, 0], &[1, 0], len(0) + len(1));
+
+        assert_route(g.shortest_route(0, 3, TravelMode::Any), &[0, 2, 3], &[2, 3], len(2) + across_2 + len(3));
+        assert_route(g.shortest_route(0, 3, TravelMode::SeaOnly), &[0, 2, 3], &[2, 3], len(2) + across_2 + len(3));
+        assert_eq!(g.shortest_route(0, 3, TravelMode::LandOnly), None);
+
+        assert_route(g.shortest_route(1, 0, TravelMode::Any), &[1, 0], &[0], len(0));
+        assert_eq!(g.shortest_route(1, 0, TravelMode::SeaOnly), None);
+
+        assert_route(g.shortest_route(3, 3, TravelMode::LandOnly), &[3], &[], 0.0);
+        assert_eq!(g.shortest_route(0, 4, TravelMode::Any), None);
+    }
+
+    #[test]
+    fn components_for_each_travel_mode() {
+        let g = graph();
+        assert_eq!(g.components(TravelMode::Any), vec![vec![0, 1, 2, 3]]);
+        assert_eq!(g.components(TravelMode::LandOnly), vec![vec![0, 1, 2], vec![3]]);
+        assert_eq!(g.components(TravelMode::SeaOnly), vec![vec![0, 2, 3], vec![1]]);
+        assert!(g.is_connected(TravelMode::Any));
+        assert!(!g.is_connected(TravelMode::LandOnly));
+        assert_eq!(g.neighbours(1), &[(0, 0), (1, 2)]);
+    }
+}
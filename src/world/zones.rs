@@ -4,14 +4,22 @@ use super::grid::{TotalGrid,TotalGridBuilder};
 use ::points::*;
 use::rand::{Rng};
 use std::collections::{HashMap};
+use super::zone_graph::{ZoneGraph,TravelMode};
 
-#[derive(Debug)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct ZoneSample {
     pt: CPoint2,
     data: PointSampleData,
     mat: Material,
 }
 
+impl ZoneSample {
+    pub fn get_pt(&self) -> CPoint2 {self.pt}
+    pub fn get_data(&self) -> &PointSampleData {&self.data}
+    pub fn get_mat(&self) -> Material {self.mat}
+}
+
+#[derive(Clone,Serialize,Deserialize)]
 pub struct Zone {
     tl: CPoint2,
     br: CPoint2,
@@ -50,6 +58,24 @@ impl Zone {
         self.samples.get_height()
     }
 
+    pub fn get_sample(&self, coord: DPoint2) -> &ZoneSample {
+        self.samples.get(coord.x as usize, coord.y as usize)
+    }
+
+    // bilinear blend of the four samples surrounding `at`, which is given
+    // in sample coordinates. ie: (1.5, 0.0) is halfway between the 2nd and 3rd sample of the top row
+    pub fn interpolate_data(&self, at: CPoint2) -> PointSampleData {
+        let max_x = (self.get_samples_per_row() - 1) as f32;
+        let max_y = (self.get_samples_per_col() - 1) as f32;
+        let (x, y) = (at.x.max(0.0).min(max_x), at.y.max(0.0).min(max_y));
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0+1).min(max_x as usize), (y0+1).min(max_y as usize));
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+        let top = self.samples.get(x0, y0).data.lerp(&self.samples.get(x1, y0).data, tx);
+        let bottom = self.samples.get(x0, y1).data.lerp(&self.samples.get(x1, y1).data, tx);
+        top.lerp(&bottom, ty)
+    }
+
     pub fn close_to_cell(&self, pt: CPoint2) -> bool {
         for (k, v) in (self.samples).into_iter() {
             if v.pt.skewed_dist_to(pt, 2.0, 1.0) < 0.003 {
@@ -87,11 +113,15 @@ impl Zone {
 
     fn shortest_sample_link(
         &self,
+        self_index: usize,
         self_taken: &[DPoint2],
         other: &Zone,
+        other_index: usize,
         other_taken: &[DPoint2],
         zones: &Vec<Zone>,
         w: &World,
+        // ignore length, terrain and other zones in the way. for when there is no other option
+        forced: bool,
     ) -> Option<WorldLink> {
         let mut shortest: Option<WorldLink> = None;
         for (m_coord, my_sample) in self.boundary_sample_iter() {
@@ -101,9 +131,11 @@ impl Zone {
                 let dist = pt_wider_dist(my_sample.pt, their_sample.pt);
 
                 //only allow links where the material allows for it
-                if my_sample.mat.is_land() != their_sample.mat.is_land()
-                || dist > WorldLink::MAX_LEN
-                || ! traversible_link(zones,  my_sample.pt, their_sample.pt, my_sample.mat.is_land(), w) {
+                if !forced && (
+                    my_sample.mat.is_land() != their_sample.mat.is_land()
+                    || dist > WorldLink::MAX_LEN
+                    || ! traversible_link(zones,  my_sample.pt, their_sample.pt, my_sample.mat.is_land(), w)
+                ) {
                     continue;
                 }
 
@@ -111,13 +143,17 @@ impl Zone {
                     if previous.length() < dist {continue}
                 }
                 shortest = Some(WorldLink {
+                    zone_a: self_index,
+                    zone_b: other_index,
                     zone_a_coord: m_coord,
                     zone_b_coord: t_coord,
                     world_a_pt: my_sample.pt,
                     world_b_pt: their_sample.pt,
                     mat_a: my_sample.mat,
                     mat_b: their_sample.mat,
-                    land_link: their_sample.mat.is_land(),
+                    // a forced link between land and water has to be sailed
+                    land_link: my_sample.mat.is_land() && their_sample.mat.is_land(),
+                    forced: forced,
                 });
             }
         }
@@ -205,8 +241,9 @@ pub fn generate_zones_for<R:Rng>(w: &World, rng: &mut R) -> Vec<Zone> {
         }
         let mut samples: TotalGridBuilder<_> = TotalGridBuilder::new();
         let mut count_walkable_materials = 0;
-        for y in 0..zone_sample_dim.x {
-            for x in 0..zone_sample_dim.y {
+        // row by row, as the grid stores them
+        for y in 0..zone_sample_dim.y {
+            for x in 0..zone_sample_dim.x {
                 let coord = DPoint2::new(x as i32,y as i32);
                 let offset = CPoint2::new(x as f32 * distance_per_x_step, y as f32 * distance_per_y_step);
                 // let (x_offset, y_offset) = (x as f32 * distance_per_x_step, y as f32 * distance_per_y_step);
@@ -240,6 +277,8 @@ pub fn generate_zones_for<R:Rng>(w: &World, rng: &mut R) -> Vec<Zone> {
 
 #[derive(Debug,Copy,Clone,PartialEq)]
 pub struct WorldLink {
+    zone_a: usize,
+    zone_b: usize,
     zone_a_coord: DPoint2,
     zone_b_coord: DPoint2,
     world_a_pt: CPoint2,
@@ -247,6 +286,8 @@ pub struct WorldLink {
     mat_a: Material,
     mat_b: Material,
     land_link: bool,
+    // made only to connect the world up, so the terrain may not allow it
+    forced: bool,
 }
 
 
@@ -266,6 +307,40 @@ impl WorldLink {
     pub fn get_world_b_pt(&self) -> CPoint2 {self.world_b_pt}
     pub fn get_mat_a(&self) -> Material{self.mat_a}
     pub fn get_mat_b(&self) -> Material{self.mat_b}
+    pub fn get_zone_a(&self) -> usize {self.zone_a}
+    pub fn get_zone_b(&self) -> usize {self.zone_b}
+    pub fn get_zone_a_coord(&self) -> DPoint2 {self.zone_a_coord}
+    pub fn get_zone_b_coord(&self) -> DPoint2 {self.zone_b_coord}
+    pub fn is_land_link(&self) -> bool {self.land_link}
+    // may be too long, or cross mountains or zones in the way. nothing checked it can be travelled
+    pub fn is_forced(&self) -> bool {self.forced}
+
+    // the boundary sample this link uses in the given zone, if it touches that zone at all
+    pub fn coord_in_zone(&self, zone_index: usize) -> Option<DPoint2> {
+        if zone_index == self.zone_a {Some(self.zone_a_coord)}
+        else if zone_index == self.zone_b {Some(self.zone_b_coord)}
+        else {None}
+    }
+}
+
+#[cfg(test)]
+impl WorldLink {
+    // for building zone graphs by hand. the boundary samples and materials are made up
+    pub fn by_hand(zone_a: usize, world_a_pt: CPoint2, zone_b: usize, world_b_pt: CPoint2, land_link: bool) -> WorldLink {
+        let mat = if land_link {Material::Grass} else {Material::Water};
+        WorldLink {
+            zone_a: zone_a,
+            zone_b: zone_b,
+            zone_a_coord: DPoint2::new(0, 0),
+            zone_b_coord: DPoint2::new(0, 0),
+            world_a_pt: world_a_pt,
+            world_b_pt: world_b_pt,
+            mat_a: mat,
+            mat_b: mat,
+            land_link: land_link,
+            forced: false,
+        }
+    }
 }
 
 pub fn generate_links_for<R:Rng>(zones: &Vec<Zone>, rng: &mut R, w : &World) -> Vec<WorldLink> {
@@ -276,7 +351,7 @@ pub fn generate_links_for<R:Rng>(zones: &Vec<Zone>, rng: &mut R, w : &World) ->
     for (i, zone_i) in zones.iter().enumerate() {
         'pair_loop: for (j, zone_j) in zones.iter().enumerate().skip(i+1) {
             if let Some(shortest) = zone_i.shortest_sample_link(
-                &taken_samples[i], zone_j, &taken_samples[j], zones, w
+                i, &taken_samples[i], zone_j, j, &taken_samples[j], zones, w, false
             ) {
                 if rng.gen_weighted_bool(6) {
                     // ignore connections randomly
@@ -292,3 +367,74 @@ pub fn generate_links_for<R:Rng>(zones: &Vec<Zone>, rng: &mut R, w : &World) ->
     }
     links
 }
+
+// adds links until every zone can be reached from every other. each new link is the
+// shortest possible one out of the first component. if no link could be travelled,
+// the shortest is forced through anyway and marked as such. if there is nowhere left
+// to put one, it gives up and the world stays disconnected (see World::is_connected)
+pub fn connect_components(zones: &Vec<Zone>, links: &mut Vec<WorldLink>, w: &World) {
+    loop {
+        let components = ZoneGraph::new(zones.len(), links).components(TravelMode::Any);
+        if components.len() <= 1 {return}
+        let mut taken_samples: Vec<Vec<DPoint2>> = zones.iter().map(|_| vec![]).collect();
+        for l in links.iter() {
+            taken_samples[l.zone_a].push(l.zone_a_coord);
+            taken_samples[l.zone_b].push(l.zone_b_coord);
+        }
+        let inside = &components[0];
+        let mut best: Option<WorldLink> = None;
+        for forced in [false, true].iter() {
+            for &i in inside.iter() {
+                for j in (0..zones.len()).filter(|j| !inside.contains(j)) {
+                    let candidate = zones[i].shortest_sample_link(
+                        i, &taken_samples[i], &zones[j], j, &taken_samples[j], zones, w, *forced
+                    );
+                    if let Some(c) = candidate {
+                        if best.map(|b| c.length() < b.length()).unwrap_or(true) {
+                            best = Some(c);
+                        }
+                    }
+                }
+            }
+            if best.is_some() {break}
+        }
+        match best {
+            Some(link) => links.push(link),
+            // only if every free boundary sample is a corner
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{World,WorldPrimitive};
+    use super::*;
+
+    #[test]
+    fn connected_worlds_are_one_component() {
+        let mut with_many_zones = 0;
+        for seed in 0..10 {
+            let w = World::new(WorldPrimitive::new(seed, 0.5, 0.5).with_connected_zones());
+            // some worlds have no zones at all
+            let components = w.zone_graph().components(TravelMode::Any);
+            assert!(components.len() <= 1, "seed {}: {:?}", seed, components);
+            assert!(w.is_connected());
+            if w.get_zones().len() > 1 {
+                with_many_zones += 1;
+            }
+        }
+        assert!(with_many_zones > 0);
+    }
+
+    #[test]
+    fn only_connecting_links_are_forced() {
+        for seed in 0..10 {
+            let w = World::new(WorldPrimitive::new(seed, 0.5, 0.5));
+            for l in w.get_links() {
+                assert!(!l.is_forced(), "seed {}: {:?}", seed, l);
+                assert!(l.length() <= WorldLink::MAX_LEN);
+            }
+        }
+    }
+}